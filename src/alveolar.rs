@@ -0,0 +1,78 @@
+use crate::types::*;
+
+// Alveolar CO2 of about 40 mmHg, 53.6 mbar so the RQ presets give their
+// published effective water vapour.
+const ALVEOLAR_CO2: f64 = 53.6;
+
+/// Alveolar gas conditions used to get the inspired inert gas pressure.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Alveolar {
+    pub water: Pressure, // water vapour at body temperature
+    pub co2: Pressure,
+    pub rq: f64, // respiratory quotient
+}
+
+impl Alveolar {
+    pub fn new(water: Pressure, co2: Pressure, rq: f64) -> Result<Alveolar, String> {
+        if rq <= 0.0 || rq > 1.0 {
+            return Err(format!("Respiratory quotient {} must be in (0, 1].", rq));
+        }
+        Ok(Alveolar { water, co2, rq })
+    }
+
+    // RQ 1.0, effective 0.0627 bar.
+    pub fn buhlmann() -> Alveolar {
+        Alveolar {
+            water: Pressure::millibar(62.7),
+            co2: Pressure::millibar(ALVEOLAR_CO2),
+            rq: 1.0,
+        }
+    }
+
+    // RQ 0.9, effective 0.0567 bar.
+    pub fn schreiner() -> Alveolar {
+        Alveolar {
+            rq: 0.9,
+            ..Alveolar::buhlmann()
+        }
+    }
+
+    // RQ 0.8, effective 0.0493 bar.
+    pub fn us_navy() -> Alveolar {
+        Alveolar {
+            rq: 0.8,
+            ..Alveolar::buhlmann()
+        }
+    }
+
+    /// Pressure to subtract from ambient before applying the inert gas fraction
+    /// (Schreiner): water - (1 - RQ) / RQ * CO2.
+    pub fn effective_water(&self) -> Pressure {
+        self.water - self.co2 * ((1.0 - self.rq) / self.rq)
+    }
+
+    /// Alveolar pressure of an inert gas with fraction f_inert at ambient.
+    pub fn inspired(&self, ambient: Pressure, f_inert: f64) -> f64 {
+        (ambient.to_bar() - self.effective_water().to_bar()) * f_inert
+    }
+}
+
+impl Default for Alveolar {
+    fn default() -> Alveolar {
+        Alveolar::buhlmann()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn presets() {
+        let water = |a: Alveolar| (a.effective_water().to_mbar() * 10.0).round() / 10.0;
+        assert_eq!(62.7, water(Alveolar::buhlmann()));
+        assert_eq!(56.7, water(Alveolar::schreiner()));
+        assert_eq!(49.3, water(Alveolar::us_navy()));
+        assert!(Alveolar::new(Pressure::millibar(62.7), Pressure::millibar(53.6), 0.0).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use crate::dive_consts::*;
+use crate::gas::{Gas, GasRole};
+use crate::types::*;
+
+// Standard deco gas switch depths in meters (35/25, 50% and O2).
+pub const DECO_SWITCH_DEPTHS: [f64; 3] = [36.0, 21.0, 6.0];
+
+#[derive(Copy, Clone)]
+pub struct MixLimits {
+    pub max_ppo2: f64,
+    pub deco_ppo2: f64,
+    pub max_end: Depth,
+    pub o2_narcotic: bool,
+    pub max_density: f64, // g/L
+}
+
+impl Default for MixLimits {
+    fn default() -> MixLimits {
+        MixLimits {
+            max_ppo2: 1.4,
+            deco_ppo2: 1.6,
+            max_end: Depth::meters(30.0),
+            o2_narcotic: true,
+            max_density: 5.2,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct BestMix {
+    pub gas: Gas,
+    pub depth: Depth,
+    pub mod_depth: Depth,
+    pub end: Depth,
+    pub density: f64, // g/L at depth
+}
+
+// Returns the f_o2/f_he (whole percents) that meet the limits at pressure.
+fn calc_mix(
+    pressure: Pressure,
+    atm: Pressure,
+    water: Water,
+    ppo2: f64,
+    limits: &MixLimits,
+) -> Result<(f64, f64), String> {
+    let f_o2 = ((ppo2 / pressure.to_bar()) * 100.0).floor().min(100.0) / 100.0;
+    let end_pressure = Pressure::from_depth(limits.max_end, atm, water);
+    let narcotic_max = end_pressure.to_mbar() / pressure.to_mbar();
+    let he_end = if limits.o2_narcotic {
+        1.0 - narcotic_max
+    } else {
+        1.0 - f_o2 - (0.79 * narcotic_max)
+    };
+    // Replace nitrogen with helium until the density limit is met.
+    let he_density = (f_o2 * DENSITY_O2 + (1.0 - f_o2) * DENSITY_N2
+        - (limits.max_density * 1.01325 / pressure.to_bar()))
+        / (DENSITY_N2 - DENSITY_HE);
+    let f_he = (he_end.max(he_density).max(0.0) * 100.0).ceil() / 100.0;
+    if f_o2 + f_he > 1.0 + f64::EPSILON {
+        return Err(format!(
+            "No mix meets ppO2 {}, END {}m and density {}g/L at {}m.",
+            ppo2,
+            limits.max_end.to_meters(),
+            limits.max_density,
+            pressure.to_depth(atm, water).to_meters()
+        ));
+    }
+    Ok((f_o2, f_he.min(1.0 - f_o2)))
+}
+
+fn make_best(gas: Gas, depth: Depth, atm: Pressure, water: Water, limits: &MixLimits) -> BestMix {
+    BestMix {
+        gas,
+        depth,
+        mod_depth: gas.mod_depth(atm, water),
+        end: gas.end(depth, atm, water, limits.o2_narcotic),
+        density: gas.density(depth, atm, water),
+    }
+}
+
+/// Finds the bottom gas with the most oxygen and least helium that stays within
+/// the ppO2, END and density limits at depth.
+pub fn best_mix(
+    depth: Depth,
+    atm: Pressure,
+    water: Water,
+    limits: &MixLimits,
+) -> Result<BestMix, String> {
+    let pressure = Pressure::from_depth(depth, atm, water);
+    let (f_o2, f_he) = calc_mix(pressure, atm, water, limits.max_ppo2, limits)?;
+    let gas = Gas::new_bottom(f_o2, f_he, limits.max_ppo2);
+    Ok(make_best(gas, depth, atm, water, limits))
+}
+
+/// Best deco gasses for each standard switch depth shallower than depth, deepest first.
+pub fn best_deco_mixes(
+    depth: Depth,
+    atm: Pressure,
+    water: Water,
+    limits: &MixLimits,
+) -> Result<Vec<BestMix>, String> {
+    let mut mixes = Vec::new();
+    for switch in DECO_SWITCH_DEPTHS.iter() {
+        let switch_depth = Depth::meters(*switch);
+        if switch_depth >= depth {
+            continue;
+        }
+        let pressure = Pressure::from_depth(switch_depth, atm, water);
+        let (f_o2, f_he) = calc_mix(pressure, atm, water, limits.deco_ppo2, limits)?;
+        let gas = Gas::new(f_o2, f_he, limits.deco_ppo2, 0.21, GasRole::Deco);
+        mixes.push(make_best(gas, switch_depth, atm, water, limits));
+    }
+    Ok(mixes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn best_mix_60m() {
+        let atm = Pressure::millibar(1013.0);
+        let best = best_mix(
+            Depth::meters(60.0),
+            atm,
+            Water::default(),
+            &MixLimits::default(),
+        )
+        .unwrap();
+        assert_eq!("19/50".to_owned(), format!("{}", best.gas));
+        assert!(best.end <= Depth::meters(30.0));
+        assert!(best.density <= 5.2);
+
+        let deco = best_deco_mixes(
+            Depth::meters(60.0),
+            atm,
+            Water::default(),
+            &MixLimits::default(),
+        )
+        .unwrap();
+        let names: Vec<String> = deco.iter().map(|m| format!("{}", m.gas)).collect();
+        assert_eq!(vec!["34/16", "51%", "99%"], names);
+        // The MOD is the gas's own, deco gasses at the deco ppO2.
+        for mix in deco.iter().chain(std::iter::once(&best)) {
+            assert!(mix.mod_depth == mix.gas.mod_depth(atm, Water::default()));
+        }
+        assert_eq!(1.6, deco[0].gas.ppo2);
+        assert!((deco[0].mod_depth.to_meters() - 36.9).abs() < 0.05);
+    }
+
+    #[test]
+    fn best_mix_impossible() {
+        let limits = MixLimits {
+            max_density: 1.0,
+            ..Default::default()
+        };
+        assert!(best_mix(
+            Depth::meters(100.0),
+            Pressure::millibar(1013.0),
+            Water::default(),
+            &limits
+        )
+        .is_err());
+    }
+}
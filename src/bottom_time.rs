@@ -0,0 +1,139 @@
+use crate::dive::*;
+use crate::dive_plan::DivePlan;
+use crate::gas::Gas;
+use crate::segment::*;
+use crate::types::*;
+
+/// A cylinder of gas, capacity is its water volume.
+#[derive(Copy, Clone)]
+pub struct Cylinder {
+    pub gas: Gas,
+    pub capacity: Volume,
+    pub pressure: Pressure, // fill pressure
+}
+
+impl Cylinder {
+    pub fn new(gas: Gas, capacity: Volume, pressure: Pressure) -> Cylinder {
+        Cylinder {
+            gas,
+            capacity,
+            pressure,
+        }
+    }
+
+    /// Surface volume usable with a thirds reserve (a third in, a third out).
+    pub fn usable_thirds(&self) -> Volume {
+        self.capacity * (self.pressure.to_bar() * 2.0 / 3.0)
+    }
+}
+
+/// Something the plan must not exceed.
+#[derive(Clone)]
+pub enum PlanLimit {
+    RunTime(u32),       // minutes
+    DecoTime(u32),      // minutes spent at stops
+    Tts(u32),           // minutes from leaving the bottom to the surface
+    Cns(f64),           // %
+    Gas(Vec<Cylinder>), // every gas used must fit in its cylinders keeping thirds
+}
+
+impl PlanLimit {
+    pub fn allows(&self, plan: &DivePlan) -> bool {
+        match self {
+            PlanLimit::RunTime(max) => plan.run_time <= *max,
+            PlanLimit::DecoTime(max) => plan.deco_time <= *max,
+            PlanLimit::Tts(max) => plan.tts <= *max,
+            PlanLimit::Cns(max) => plan.otu_cns.cns <= *max,
+            PlanLimit::Gas(cylinders) => plan.gas_usage.iter().all(|(gas, used)| {
+                let usable: f64 = cylinders
+                    .iter()
+                    .filter(|c| c.gas == *gas)
+                    .map(|c| c.usable_thirds().to_liters())
+                    .sum();
+                used.to_liters() <= usable
+            }),
+        }
+    }
+}
+
+/// Longest bottom time in minutes (including the descent, up to max_time) at
+/// depth that meets every limit, None if even the descent alone does not.
+#[allow(clippy::too_many_arguments)]
+pub fn max_bottom_time(
+    dive: &Dive,
+    compartments: &Compartments,
+    constants: &TissueConstants,
+    depth: Depth,
+    setpoint: f64,
+    gasses: &[Gas],
+    limits: &[PlanLimit],
+    max_time: u32,
+) -> Result<Option<u32>, String> {
+    let allowed = |time: u32| -> Result<bool, String> {
+        let segments = [SegmentIn::new_bottom(depth, time as f64, setpoint)];
+        let plan = calc_deco(dive, compartments, constants, &segments, gasses)?;
+        Ok(limits.iter().all(|l| l.allows(&plan)))
+    };
+    let descent = (Pressure::from_depth(depth, dive.atm_pressure, dive.water) - dive.atm_pressure)
+        .to_mbar()
+        / dive.descent_rate.to_mbar();
+    let mut low = (descent.ceil() as u32).max(1);
+    if low > max_time || !allowed(low)? {
+        return Ok(None);
+    }
+    // Every limit only gets worse with more time so bisect.
+    let mut high = max_time + 1;
+    while high - low > 1 {
+        let mid = (low + high) / 2;
+        if allowed(mid)? {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    Ok(Some(low))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn reverse() {
+        let dive: Dive = Default::default();
+        let comps = Compartments::new_surface(1013.0, Alveolar::default(), COMPARTMENTS);
+        let bottom = Gas::new_bottom(0.18, 0.45, 1.4);
+        let deco = Gas::new_deco(0.5, 0.0);
+        let gasses = vec![bottom, deco];
+        let depth = Depth::meters(60.0);
+        let max = |limits: &[PlanLimit]| {
+            max_bottom_time(
+                &dive,
+                &comps,
+                &CONSTANTS_C,
+                depth,
+                1.4,
+                &gasses,
+                limits,
+                120,
+            )
+            .unwrap()
+        };
+        let time = max(&[PlanLimit::Tts(60)]).unwrap();
+        let tts = |t: u32| {
+            let segments = [SegmentIn::new_bottom(depth, t as f64, 1.4)];
+            calc_deco_c(&dive, &comps, &segments, &gasses).unwrap().tts
+        };
+        assert!(tts(time) <= 60 && tts(time + 1) > 60);
+        assert_eq!(Some(120), max(&[PlanLimit::RunTime(1000)]));
+        assert_eq!(None, max(&[PlanLimit::RunTime(5)]));
+
+        // Double 12s of 200 bar and an 11L of deco gas.
+        let cylinders = vec![
+            Cylinder::new(bottom, Volume::liters(24.0), Pressure::bar(200.0)),
+            Cylinder::new(deco, Volume::liters(11.0), Pressure::bar(200.0)),
+        ];
+        let gas_time = max(&[PlanLimit::Gas(cylinders)]).unwrap();
+        assert!(gas_time > 10 && gas_time < time);
+    }
+}
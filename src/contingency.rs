@@ -0,0 +1,125 @@
+use crate::dive::*;
+use crate::dive_plan::DivePlan;
+use crate::gas::Gas;
+use crate::segment::*;
+use crate::types::*;
+
+/// A change to the planned dive to plan for.
+#[derive(Copy, Clone)]
+pub enum Contingency {
+    Deeper(Depth),            // every level this much deeper
+    Longer(f64),              // minutes more at the deepest level
+    DeeperLonger(Depth, f64), // both
+    LostGas(Gas),             // gas not available on the dive
+}
+
+impl Contingency {
+    pub fn label(&self, dive: &Dive) -> String {
+        match self {
+            Contingency::Deeper(depth) => format!("+{}", dive.format_depth(*depth)),
+            Contingency::Longer(time) => format!("+{} min", time),
+            Contingency::DeeperLonger(depth, time) => {
+                format!("+{} / +{} min", dive.format_depth(*depth), time)
+            }
+            Contingency::LostGas(gas) => format!("Lost {}", gas),
+        }
+    }
+
+    // The profile and gasses with the contingency applied.
+    fn apply(&self, segments_in: &[SegmentIn], gasses: &[Gas]) -> (Vec<SegmentIn>, Vec<Gas>) {
+        let mut segments = segments_in.to_vec();
+        let mut gasses = gasses.to_vec();
+        let (deeper, longer) = match self {
+            Contingency::Deeper(depth) => (Some(*depth), 0.0),
+            Contingency::Longer(time) => (None, *time),
+            Contingency::DeeperLonger(depth, time) => (Some(*depth), *time),
+            Contingency::LostGas(gas) => {
+                gasses.retain(|g| g != gas);
+                (None, 0.0)
+            }
+        };
+        if let Some(depth) = deeper {
+            for s in segments.iter_mut() {
+                s.depth += depth;
+            }
+        }
+        if longer > 0.0 {
+            let deepest = segments
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.depth.to_mm().total_cmp(&b.depth.to_mm()))
+                .map(|(i, _)| i);
+            if let Some(i) = deepest {
+                segments[i].time += longer;
+            }
+        }
+        (segments, gasses)
+    }
+}
+
+pub struct ContingencyPlan {
+    pub label: String,
+    pub contingency: Option<Contingency>, // None for the planned dive
+    pub plan: Result<DivePlan, String>,
+}
+
+/// The planned dive followed by a plan for each contingency, a contingency
+/// that can not be planned (no breathable gas...) keeps its error.
+pub fn contingency_plans(
+    dive: &Dive,
+    compartments: &Compartments,
+    constants: &TissueConstants,
+    segments_in: &[SegmentIn],
+    gasses: &[Gas],
+    contingencies: &[Contingency],
+) -> Result<Vec<ContingencyPlan>, String> {
+    let planned = calc_deco(dive, compartments, constants, segments_in, gasses)?;
+    let mut plans = vec![ContingencyPlan {
+        label: "Planned".to_string(),
+        contingency: None,
+        plan: Ok(planned),
+    }];
+    for c in contingencies {
+        let (segments, gasses) = c.apply(segments_in, gasses);
+        plans.push(ContingencyPlan {
+            label: c.label(dive),
+            contingency: Some(*c),
+            plan: calc_deco(dive, compartments, constants, &segments, &gasses),
+        });
+    }
+    Ok(plans)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn contingencies() {
+        let dive: Dive = Default::default();
+        let comps = Compartments::new_surface(1013.0, Alveolar::default(), COMPARTMENTS);
+        let segments = vec![SegmentIn::new_bottom(Depth::meters(45.0), 25.0, 1.4)];
+        let deco = Gas::new_deco(0.5, 0.0);
+        let gasses = vec![Gas::new_bottom(0.21, 0.35, 1.4), deco];
+        let plans = contingency_plans(
+            &dive,
+            &comps,
+            &CONSTANTS_C,
+            &segments,
+            &gasses,
+            &[
+                Contingency::DeeperLonger(Depth::meters(3.0), 5.0),
+                Contingency::LostGas(deco),
+                Contingency::Deeper(Depth::meters(100.0)),
+            ],
+        )
+        .unwrap();
+        let labels: Vec<&str> = plans.iter().map(|p| p.label.as_str()).collect();
+        assert_eq!(vec!["Planned", "+3m / +5 min", "Lost 50%", "+100m"], labels);
+        let run_time = |i: usize| plans[i].plan.as_ref().unwrap().run_time;
+        assert!(run_time(1) > run_time(0));
+        assert!(run_time(2) > run_time(0));
+        // Nothing to breathe at 145m.
+        assert!(plans[3].plan.is_err());
+    }
+}
@@ -1,10 +1,14 @@
+use std::borrow::Cow;
+
+use crate::alveolar::Alveolar;
 use crate::dive_consts::*;
-use crate::gas::Gas;
+use crate::dive_plan::DivePlan;
+use crate::gas::*;
 use crate::segment::*;
 use crate::segment_type::SegmentType;
 use crate::types::*;
 
-//use otu_cns::OtuCns;
+use crate::otu_cns::{CnsModel, OtuCns, OxygenExposure};
 
 #[derive(Copy, Clone)]
 pub enum DiveType {
@@ -13,53 +17,204 @@ pub enum DiveType {
     //    SCR
 }
 
+/// How a compartment holding both N2 and He combines the two a/b sets.
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub enum CoefficientMix {
+    /// a and b weighted by the partial pressures (Bühlmann).
+    #[default]
+    Weighted,
+    /// a and 1/b weighted, same as weighting Workman M0 and ΔM (Baker).
+    Baker,
+    /// Whichever set gives the lower M-value at the compartment's tension.
+    WorstOf,
+}
+
 pub const CONSTANTS_A: TissueConstants = TissueConstants {
-    half_times_n2: &HALF_TIMES_N2,
-    half_times_he: &HALF_TIMES_HE,
-    he_a_s: &HE_AS,
-    he_b_s: &HE_BS,
-    n2_a_s: &N2AS_A,
-    n2_b_s: &N2BS,
+    half_times_n2: Cow::Borrowed(&HALF_TIMES_N2),
+    half_times_he: Cow::Borrowed(&HALF_TIMES_HE),
+    he_a_s: Cow::Borrowed(&HE_AS),
+    he_b_s: Cow::Borrowed(&HE_BS),
+    n2_a_s: Cow::Borrowed(&N2AS_A),
+    n2_b_s: Cow::Borrowed(&N2BS),
     compartments: COMPARTMENTS,
 };
 
 pub const CONSTANTS_B: TissueConstants = TissueConstants {
-    half_times_n2: &HALF_TIMES_N2,
-    half_times_he: &HALF_TIMES_HE,
-    he_a_s: &HE_AS,
-    he_b_s: &HE_BS,
-    n2_a_s: &N2AS_B,
-    n2_b_s: &N2BS,
+    half_times_n2: Cow::Borrowed(&HALF_TIMES_N2),
+    half_times_he: Cow::Borrowed(&HALF_TIMES_HE),
+    he_a_s: Cow::Borrowed(&HE_AS),
+    he_b_s: Cow::Borrowed(&HE_BS),
+    n2_a_s: Cow::Borrowed(&N2AS_B),
+    n2_b_s: Cow::Borrowed(&N2BS),
     compartments: COMPARTMENTS,
 };
 
 pub const CONSTANTS_C: TissueConstants = TissueConstants {
-    half_times_n2: &HALF_TIMES_N2,
-    half_times_he: &HALF_TIMES_HE,
-    he_a_s: &HE_AS,
-    he_b_s: &HE_BS,
-    n2_a_s: &N2AS_C,
-    n2_b_s: &N2BS,
+    half_times_n2: Cow::Borrowed(&HALF_TIMES_N2),
+    half_times_he: Cow::Borrowed(&HALF_TIMES_HE),
+    he_a_s: Cow::Borrowed(&HE_AS),
+    he_b_s: Cow::Borrowed(&HE_BS),
+    n2_a_s: Cow::Borrowed(&N2AS_C),
+    n2_b_s: Cow::Borrowed(&N2BS),
     compartments: COMPARTMENTS,
 };
 
 pub struct TissueConstants<'a> {
-    pub half_times_n2: &'a [f64], // = halfTimesN2;
-    pub half_times_he: &'a [f64], // = halfTimesHe;
-    pub he_a_s: &'a [f64],
-    pub he_b_s: &'a [f64],
-    pub n2_a_s: &'a [f64],
-    pub n2_b_s: &'a [f64],
+    pub half_times_n2: Cow<'a, [f64]>, // = halfTimesN2;
+    pub half_times_he: Cow<'a, [f64]>, // = halfTimesHe;
+    pub he_a_s: Cow<'a, [f64]>,
+    pub he_b_s: Cow<'a, [f64]>,
+    pub n2_a_s: Cow<'a, [f64]>,
+    pub n2_b_s: Cow<'a, [f64]>,
     pub compartments: usize,
 }
 
-/*
+impl TissueConstants<'static> {
+    /// Custom tissue table (ZH-L12, ZH-L8 ADT, research sets...), every table
+    /// must have one entry per compartment.
+    pub fn new(
+        half_times_n2: Vec<f64>,
+        half_times_he: Vec<f64>,
+        n2_a_s: Vec<f64>,
+        n2_b_s: Vec<f64>,
+        he_a_s: Vec<f64>,
+        he_b_s: Vec<f64>,
+    ) -> Result<TissueConstants<'static>, String> {
+        let compartments = half_times_n2.len();
+        if compartments == 0 {
+            return Err("Tissue constants need at least one compartment.".to_string());
+        }
+        let tables = [
+            ("He half-times", &half_times_he),
+            ("N2 a", &n2_a_s),
+            ("N2 b", &n2_b_s),
+            ("He a", &he_a_s),
+            ("He b", &he_b_s),
+        ];
+        for (name, table) in tables {
+            if table.len() != compartments {
+                return Err(format!(
+                    "{} has {} entries, expected {}.",
+                    name,
+                    table.len(),
+                    compartments
+                ));
+            }
+        }
+        let positive = |t: &[f64]| t.iter().all(|v| *v > 0.0);
+        if !positive(&half_times_n2)
+            || !positive(&half_times_he)
+            || !positive(&n2_b_s)
+            || !positive(&he_b_s)
+        {
+            return Err("Half-times and b coefficients must be positive.".to_string());
+        }
+        Ok(TissueConstants {
+            half_times_n2: Cow::Owned(half_times_n2),
+            half_times_he: Cow::Owned(half_times_he),
+            he_a_s: Cow::Owned(he_a_s),
+            he_b_s: Cow::Owned(he_b_s),
+            n2_a_s: Cow::Owned(n2_a_s),
+            n2_b_s: Cow::Owned(n2_b_s),
+            compartments,
+        })
+    }
 
-   Fresh Water = 1000kg/m³
-   EN13319 = 1020 kg/m³
-   Salt Water = 1030 kg/m³
+    /// Tissue table with a and b derived from the half-times using Bühlmann's
+    /// formulas a = 2·t^(-1/3) and b = 1.005 - t^(-1/2).
+    pub fn from_half_times(
+        half_times_n2: Vec<f64>,
+        half_times_he: Vec<f64>,
+    ) -> Result<TissueConstants<'static>, String> {
+        let a_s = |t: &[f64]| t.iter().map(|t| 2.0 * t.powf(-1.0 / 3.0)).collect();
+        let b_s = |t: &[f64]| t.iter().map(|t| 1.005 - t.powf(-0.5)).collect();
+        let n2_a_s = a_s(&half_times_n2);
+        let n2_b_s = b_s(&half_times_n2);
+        let he_a_s = a_s(&half_times_he);
+        let he_b_s = b_s(&half_times_he);
+        TissueConstants::new(half_times_n2, half_times_he, n2_a_s, n2_b_s, he_a_s, he_b_s)
+    }
+}
+
+/// Converts Workman M0 (bar at the surface) and ΔM (bar/bar) to Bühlmann a and b.
+pub fn workman_to_buhlmann(m0: f64, delta_m: f64, surface: Pressure) -> (f64, f64) {
+    (m0 - surface.to_bar() * delta_m, 1.0 / delta_m)
+}
+
+/// Converts Bühlmann a and b to Workman M0 (bar at the surface) and ΔM.
+pub fn buhlmann_to_workman(a: f64, b: f64, surface: Pressure) -> (f64, f64) {
+    (a + surface.to_bar() / b, 1.0 / b)
+}
+
+// Most entries a GfTable can hold (96m of 3m stops).
+pub const GF_TABLE_SIZE: usize = 32;
+
+/// GF per stop depth, fixed size so Dive stays Copy.
+#[derive(Copy, Clone, PartialEq)]
+pub struct GfTable {
+    entries: [(Depth, f64); GF_TABLE_SIZE],
+    len: usize,
+}
+
+impl GfTable {
+    pub fn new(entries: &[(Depth, f64)]) -> Result<GfTable, String> {
+        if entries.len() > GF_TABLE_SIZE {
+            return Err(format!(
+                "GF table has {} entries, at most {} allowed.",
+                entries.len(),
+                GF_TABLE_SIZE
+            ));
+        }
+        let mut table = GfTable {
+            entries: [(Depth::meters(0.0), 0.0); GF_TABLE_SIZE],
+            len: entries.len(),
+        };
+        table.entries[..entries.len()].copy_from_slice(entries);
+        Ok(table)
+    }
+
+    pub fn entries(&self) -> &[(Depth, f64)] {
+        &self.entries[..self.len]
+    }
+}
+
+/// How the GF moves from gf_lo to gf_hi during the ascent.
+// The table is inline, not boxed, so Dive stays Copy.
+#[allow(clippy::large_enum_variant)]
+#[derive(Copy, Clone, Default, PartialEq)]
+pub enum GfSchedule {
+    /// gf_lo at the first stop, linear in depth to gf_hi at the surface.
+    #[default]
+    Linear,
+    /// gf_lo at a fixed depth (and below), linear to gf_hi at the surface.
+    Anchored(Depth),
+    /// GF per stop depth, a stop uses the shallowest entry at or below it and
+    /// gf_lo when deeper than every entry.
+    Table(GfTable),
+}
+
+/// Optional stops deeper than the first stop the GFs call for.
+#[derive(Copy, Clone, Default, PartialEq)]
+pub enum DeepStops {
+    #[default]
+    None,
+    /// Pyle stops half way to the first stop, repeated while they fit.
+    Pyle { time: f64 },
+    /// Baker's deep stops from ratio of the way between the first stop and
+    /// the off gassing depth of the leading tissue.
+    Baker { ratio: f64, time: f64 },
+}
+
+impl DeepStops {
+    /// Minutes at each deep stop.
+    pub fn time(&self) -> f64 {
+        match self {
+            DeepStops::None => 0.0,
+            DeepStops::Pyle { time } | DeepStops::Baker { time, .. } => *time,
+        }
+    }
+}
 
-*/
 /*
 Use mm for distance (10ft = 3048mm)
 Use mbar for pressure.
@@ -68,6 +223,9 @@ Use mbar for pressure.
 pub struct Dive {
     pub gf_lo: f64,
     pub gf_hi: f64,
+    pub gf_schedule: GfSchedule,
+    pub gf_surface: Option<f64>, // GF to leave the last stop, else from the schedule.
+    pub deep_stops: DeepStops,
     pub dive_type: DiveType,
     pub deco_setpoint: f64,
     pub ascent_rate: DepthChange,
@@ -75,25 +233,142 @@ pub struct Dive {
     pub atm_pressure: Pressure,
     pub last_stop: Pressure,
     pub stop_size: Pressure,
-    pub metric: bool,
-    pub partial_water: f64,
+    pub metric: bool, // display units, with_imperial() also sets imperial stops and rates
+    pub water: Water,
+    pub alveolar: Alveolar,
+    pub max_end: Depth,
+    pub o2_narcotic: bool,
+    pub max_density: f64,             // g/L
+    pub travel_switch: Option<Depth>, // Switch from travel to bottom gas here, else at its min depth.
+    pub max_cns: f64,                 // %
+    pub max_otu: f64,                 // per day
+    pub start_cns: f64,               // % left from earlier dives, see with_exposure()
+    pub start_otu: f64,               // already taken today
+    pub cns_model: CnsModel,
+    pub coefficient_mix: CoefficientMix,
+    pub sac_bottom: Volume, // per minute at the surface
+    pub sac_deco: Volume,   // per minute at the surface
 }
 
 impl Default for Dive {
     fn default() -> Dive {
         let atm = Pressure::millibar(1013.0);
+        let water = Water::default();
         Dive {
             gf_lo: 0.5,
             gf_hi: 0.8,
+            gf_schedule: GfSchedule::default(),
+            gf_surface: None,
+            deep_stops: DeepStops::default(),
             dive_type: DiveType::OC,
             deco_setpoint: 1.3,
-            ascent_rate: DepthChange::ascent_depth(Depth::meters(10.0)),
-            descent_rate: DepthChange::descent_depth(Depth::meters(18.0)),
+            ascent_rate: DepthChange::ascent_depth(Depth::meters(10.0), water),
+            descent_rate: DepthChange::descent_depth(Depth::meters(18.0), water),
             atm_pressure: atm,
-            last_stop: Pressure::from_depth(Depth::meters(3.0), atm),
-            stop_size: Pressure::from_depth_rel(Depth::meters(3.0)),
+            last_stop: Pressure::from_depth(Depth::meters(3.0), atm, water),
+            stop_size: Pressure::from_depth_rel(Depth::meters(3.0), water),
             metric: true,
-            partial_water: PARTIAL_WATER,
+            water,
+            alveolar: Alveolar::default(),
+            max_end: Depth::meters(30.0),
+            o2_narcotic: true,
+            max_density: 5.2,
+            travel_switch: None,
+            max_cns: 80.0,
+            max_otu: 300.0,
+            start_cns: 0.0,
+            start_otu: 0.0,
+            cns_model: CnsModel::default(),
+            coefficient_mix: CoefficientMix::default(),
+            sac_bottom: Volume::liters(20.0),
+            sac_deco: Volume::liters(15.0),
+        }
+    }
+}
+
+impl Dive {
+    // Same dive at altitude (meters above sea level), surface pressure from the
+    // barometric formula and the last stop kept at its depth.
+    pub fn with_altitude(self, altitude: f64) -> Dive {
+        let atm = Pressure::from_altitude(altitude);
+        let last_stop = self.last_stop.to_depth(self.atm_pressure, self.water);
+        Dive {
+            atm_pressure: atm,
+            last_stop: Pressure::from_depth(last_stop, atm, self.water),
+            ..self
+        }
+    }
+
+    // Same dive in imperial units, 10ft stops with a 10ft last stop, 30ft/min
+    // ascent, 60ft/min descent and a 100ft END.
+    pub fn with_imperial(self) -> Dive {
+        Dive {
+            ascent_rate: DepthChange::ascent_depth(Depth::feet(30.0), self.water),
+            descent_rate: DepthChange::descent_depth(Depth::feet(60.0), self.water),
+            last_stop: Pressure::from_depth(Depth::feet(10.0), self.atm_pressure, self.water),
+            stop_size: Pressure::from_depth_rel(Depth::feet(10.0), self.water),
+            metric: false,
+            max_end: Depth::feet(100.0),
+            ..self
+        }
+    }
+
+    // Same dive in water, stops and rates keep their depths in the new water.
+    pub fn with_water(self, water: Water) -> Result<Dive, String> {
+        let water = water.validate()?;
+        let depth = |p: Pressure| p.to_depth(self.atm_pressure, self.water);
+        let rate = |r: DepthChange| r.to_depth(self.water).to_mm() / water.mm_per_mbar();
+        Ok(Dive {
+            last_stop: Pressure::from_depth(depth(self.last_stop), self.atm_pressure, water),
+            stop_size: Pressure::from_depth_rel(
+                Depth::from_pressure(self.stop_size, Pressure::millibar(0.0), self.water),
+                water,
+            ),
+            ascent_rate: DepthChange::descent_pressure(Pressure::millibar(rate(self.ascent_rate))),
+            descent_rate: DepthChange::descent_pressure(Pressure::millibar(rate(
+                self.descent_rate,
+            ))),
+            water,
+            ..self
+        })
+    }
+
+    // Same dive with the last stop at depth (e.g. 20ft or 6m).
+    pub fn with_last_stop(self, depth: Depth) -> Dive {
+        Dive {
+            last_stop: Pressure::from_depth(depth, self.atm_pressure, self.water),
+            ..self
+        }
+    }
+
+    // Same dive following the series so far, its oxygen warnings include the
+    // CNS still loaded and the OTUs already taken today.
+    pub fn with_exposure(self, exposure: &OxygenExposure) -> Dive {
+        Dive {
+            start_cns: exposure.cns,
+            start_otu: exposure.otu_today(),
+            ..self
+        }
+    }
+
+    pub fn format_depth(&self, depth: Depth) -> String {
+        depth.format(self.metric, 0)
+    }
+
+    pub fn format_pressure(&self, pressure: Pressure) -> String {
+        pressure.format(self.metric, if self.metric { 1 } else { 0 })
+    }
+
+    pub fn format_rate(&self, rate: DepthChange) -> String {
+        let speed = DepthChange::descent_pressure(Pressure::millibar(rate.to_mbar().abs()));
+        speed.format(self.metric, 0, self.water)
+    }
+
+    pub fn format_volume(&self, volume: Volume) -> String {
+        if self.metric {
+            format!("{:.0}L", volume.to_liters())
+        } else {
+            format!("{:.1}cuft", volume.to_cubic_feet())
         }
     }
 }
@@ -107,13 +382,17 @@ fn find_gas_for_setpoint(dil: Gas, setpoint: f64, atm: Pressure) -> Gas {
     Gas::new_bottom(o2percent, he_percent, setpoint)
 }
 
-fn find_ocgas(gasses: &[Gas], depth: Pressure, segment_type: SegmentType) -> Gas {
+// Richest gas usable at depth, travel gasses only if nothing else is for level
+// and descent segments.
+fn find_ocgas(gasses: &[Gas], depth: Pressure, segment_type: SegmentType) -> Option<Gas> {
     let mut ret: Option<Gas> = None;
     for g in gasses {
         if g.use_gas(depth, segment_type) {
             match ret {
                 Some(rgas) => {
-                    if g.f_o2 > rgas.f_o2 {
+                    let travel = segment_type != SegmentType::UP
+                        && (g.role == GasRole::Travel) != (rgas.role == GasRole::Travel);
+                    if (travel && rgas.role == GasRole::Travel) || (!travel && g.f_o2 > rgas.f_o2) {
                         ret = Some(*g);
                     }
                 }
@@ -121,25 +400,7 @@ fn find_ocgas(gasses: &[Gas], depth: Pressure, segment_type: SegmentType) -> Gas
             }
         }
     }
-    if ret.is_none() {
-        for g in gasses {
-            if g.use_gas(depth, segment_type) {
-                match ret {
-                    Some(rgas) => {
-                        if g.f_o2 * depth.to_mbar() < rgas.f_o2 * depth.to_mbar() {
-                            ret = Some(*g);
-                        }
-                    }
-                    None => ret = Some(*g),
-                }
-            }
-        }
-    }
-    // Seem to have no gasses... Return good old air.
-    match ret {
-        Some(gas) => gas,
-        None => Gas::new_bottom(0.21, 0.0, 1.4),
-    }
+    ret
 }
 
 pub fn find_gas(
@@ -148,7 +409,7 @@ pub fn find_gas(
     depth: Pressure,
     segment_type: SegmentType,
     setpoint: f64,
-) -> Gas {
+) -> Result<Gas, String> {
     match dive.dive_type {
         DiveType::CCR => {
             let mut dil = Gas::new_bottom(0.21, 0.0, 1.4);
@@ -157,42 +418,210 @@ pub fn find_gas(
                     dil = *g;
                 }
             }
-            find_gas_for_setpoint(dil, setpoint, depth)
+            Ok(find_gas_for_setpoint(dil, setpoint, depth))
         }
-        DiveType::OC => find_ocgas(gasses, depth, segment_type),
+        DiveType::OC => find_ocgas(gasses, depth, segment_type).ok_or_else(|| {
+            format!(
+                "No breathable gas at {}.",
+                dive.format_depth(depth.to_depth(dive.atm_pressure, dive.water))
+            )
+        }),
+    }
+}
+
+// END and density at depth along with any warnings for exceeding the dive's limits.
+fn gas_limits(dive: &Dive, gas: Gas, depth: Pressure) -> (Depth, f64, Vec<String>) {
+    let depth = depth.to_depth(dive.atm_pressure, dive.water);
+    let end = gas.end(depth, dive.atm_pressure, dive.water, dive.o2_narcotic);
+    let density = gas.density(depth, dive.atm_pressure, dive.water);
+    let mut warnings = Vec::new();
+    if end > dive.max_end {
+        warnings.push(format!(
+            "END {} exceeds {} on {} at {}.",
+            dive.format_depth(end),
+            dive.format_depth(dive.max_end),
+            gas,
+            dive.format_depth(depth)
+        ));
     }
+    if density > dive.max_density {
+        warnings.push(format!(
+            "Gas density {:.2}g/L exceeds {:.2}g/L on {} at {}.",
+            density,
+            dive.max_density,
+            gas,
+            dive.format_depth(depth)
+        ));
+    }
+    (end, density, warnings)
 }
 
-fn next_gf(gf_slope: f64, dive: &Dive, stop: Pressure) -> f64 {
-    if stop.to_mbar() - dive.stop_size.to_mbar() - dive.atm_pressure.to_mbar() < 0.0 {
+// GF on the line from gf_lo at anchor to gf_hi at the surface.
+fn linear_gf(dive: &Dive, anchor: Pressure, depth: Pressure) -> f64 {
+    let depth = depth.to_mbar() - dive.atm_pressure.to_mbar();
+    let anchor = anchor.to_mbar() - dive.atm_pressure.to_mbar();
+    if depth < 0.0 || anchor <= 0.0 {
         dive.gf_hi
+    } else if depth >= anchor {
+        dive.gf_lo
     } else {
-        (gf_slope * (stop.to_mbar() - dive.stop_size.to_mbar() - dive.atm_pressure.to_mbar()))
-            + dive.gf_hi
+        dive.gf_hi - (dive.gf_hi - dive.gf_lo) * depth / anchor
     }
 }
 
-fn calc_ceiling(
+// GF used to find the first stop while ascending from depth.
+fn ascent_gf(dive: &Dive, depth: Pressure) -> f64 {
+    match &dive.gf_schedule {
+        GfSchedule::Anchored(anchor) => linear_gf(
+            dive,
+            Pressure::from_depth(*anchor, dive.atm_pressure, dive.water),
+            depth,
+        ),
+        GfSchedule::Linear | GfSchedule::Table(_) => dive.gf_lo,
+    }
+}
+
+// GF used to leave the stop at stop given the first stop of the dive.
+fn next_gf(dive: &Dive, first_stop: Pressure, stop: Pressure) -> f64 {
+    if let Some(gf) = dive.gf_surface {
+        if stop <= dive.last_stop {
+            return gf;
+        }
+    }
+    match &dive.gf_schedule {
+        GfSchedule::Linear => linear_gf(dive, first_stop, stop - dive.stop_size),
+        GfSchedule::Anchored(anchor) => linear_gf(
+            dive,
+            Pressure::from_depth(*anchor, dive.atm_pressure, dive.water),
+            stop - dive.stop_size,
+        ),
+        GfSchedule::Table(table) => {
+            let depth = stop.to_depth(dive.atm_pressure, dive.water).to_mm();
+            // Shallowest entry at or below the stop (to the nearest cm).
+            table
+                .entries()
+                .iter()
+                .filter(|(d, _)| d.to_mm() + 10.0 >= depth)
+                .min_by(|(a, _), (b, _)| a.to_mm().total_cmp(&b.to_mm()))
+                .map_or(dive.gf_lo, |(_, gf)| *gf)
+        }
+    }
+}
+
+// Ceiling of the tissues without clamping to the surface.
+pub(crate) fn tissue_ceiling(
     comps: &Compartments,
-    atm: Pressure,
     constants: &TissueConstants,
+    mix: CoefficientMix,
     gf: f64,
-) -> Pressure // Depth of current ceiling.
-{
+) -> Pressure {
+    Pressure::bar(leading_compartment(comps, constants, mix, gf).1)
+}
+
+// Bühlmann a and b for compartment i combining the N2 and He sets by mix.
+// A compartment with no inert gas uses the N2 set.
+fn coefficients(
+    comps: &Compartments,
+    constants: &TissueConstants,
+    mix: CoefficientMix,
+    i: usize,
+) -> (f64, f64) {
+    let n2 = comps.nitrogen[i];
+    let he = comps.helium[i];
+    let n2_ab = (constants.n2_a_s[i], constants.n2_b_s[i]);
+    let he_ab = (constants.he_a_s[i], constants.he_b_s[i]);
+    let load = n2 + he;
+    if load <= 0.0 || he <= 0.0 {
+        return n2_ab;
+    }
+    if n2 <= 0.0 {
+        return he_ab;
+    }
+    match mix {
+        CoefficientMix::Weighted => (
+            (n2_ab.0 * n2 + he_ab.0 * he) / load,
+            (n2_ab.1 * n2 + he_ab.1 * he) / load,
+        ),
+        CoefficientMix::Baker => (
+            (n2_ab.0 * n2 + he_ab.0 * he) / load,
+            load / (n2 / n2_ab.1 + he / he_ab.1),
+        ),
+        CoefficientMix::WorstOf => {
+            let m_value = |(a, b): (f64, f64)| load / b + a;
+            if m_value(he_ab) < m_value(n2_ab) {
+                he_ab
+            } else {
+                n2_ab
+            }
+        }
+    }
+}
+
+// Index of the compartment with the deepest ceiling and that ceiling in bar.
+fn leading_compartment(
+    comps: &Compartments,
+    constants: &TissueConstants,
+    mix: CoefficientMix,
+    gf: f64,
+) -> (usize, f64) {
+    let mut leading = 0;
     let mut ceiling = 0.0;
     for i in 0..constants.compartments {
-        let a = ((constants.n2_a_s[i] * comps.nitrogen[i])
-            + (constants.he_a_s[i] * comps.helium[i]))
-            / (comps.nitrogen[i] + comps.helium[i]);
-        let b = ((constants.n2_b_s[i] * comps.nitrogen[i])
-            + (constants.he_b_s[i] * comps.helium[i]))
-            / (comps.nitrogen[i] + comps.helium[i]);
+        let (a, b) = coefficients(comps, constants, mix, i);
         let ceil = ((comps.nitrogen[i] + comps.helium[i]) - (gf * a)) / ((gf / b) - gf + 1.0);
         if ceil > ceiling {
+            leading = i;
             ceiling = ceil
         };
     }
-    let stop = Pressure::bar(ceiling);
+    (leading, ceiling)
+}
+
+// Highest supersaturation of any compartment at ambient as a percentage of
+// its M-value (0 when every compartment is at or below ambient).
+fn max_gradient(
+    comps: &Compartments,
+    constants: &TissueConstants,
+    mix: CoefficientMix,
+    ambient: Pressure,
+) -> f64 {
+    let ambient = ambient.to_bar();
+    let mut gradient: f64 = 0.0;
+    for i in 0..constants.compartments {
+        let (a, b) = coefficients(comps, constants, mix, i);
+        let m_value = ambient / b + a;
+        let load = comps.nitrogen[i] + comps.helium[i];
+        gradient = gradient.max((load - ambient) / (m_value - ambient) * 100.0);
+    }
+    gradient
+}
+
+// Fills in the ceiling at gf, leading compartment, GF99 and surfacing GF
+// from the tissues at the end of the segment.
+pub(crate) fn tissue_state(
+    dive: &Dive,
+    constants: &TissueConstants,
+    segment: &mut Segment,
+    gf: f64,
+) {
+    let comps = &segment.compartments;
+    let mix = dive.coefficient_mix;
+    let (leading, ceiling) = leading_compartment(comps, constants, mix, gf);
+    segment.ceiling = Pressure::bar(ceiling).max(dive.atm_pressure);
+    segment.leading_compartment = leading;
+    segment.gf99 = max_gradient(comps, constants, mix, segment.depth);
+    segment.surface_gf = max_gradient(comps, constants, mix, dive.atm_pressure);
+}
+
+fn calc_ceiling(
+    comps: &Compartments,
+    atm: Pressure,
+    constants: &TissueConstants,
+    mix: CoefficientMix,
+    gf: f64,
+) -> Pressure // Depth of current ceiling.
+{
+    let stop = tissue_ceiling(comps, constants, mix, gf);
     if stop < atm {
         atm
     } else {
@@ -204,32 +633,31 @@ fn calc_tissue_bottom(
     tissue_in: f64,
     time: f64,
     depth: Pressure,
-    partial_water: f64,
+    alveolar: Alveolar,
     partial_pressure: f64,
     half_time: f64,
 ) -> f64 {
-    let bar = depth.to_bar();
     let po = tissue_in;
-    let pio = (bar - (partial_water / 1000.0)) * partial_pressure;
-    po + (pio - po) * (1.0 - (2.0 as f64).powf(-time / half_time))
+    let pio = alveolar.inspired(depth, partial_pressure);
+    po + (pio - po) * (1.0 - 2.0_f64.powf(-time / half_time))
 }
 
-fn calc_bottom(
+pub(crate) fn calc_bottom(
     comps_in: &Compartments,
     constants: &TissueConstants,
-    partial_water: f64,
+    alveolar: Alveolar,
     depth: Pressure,
     time: f64,
     gas: Gas,
 ) -> Compartments {
-    let mut comps_out = Compartments::new_empty(COMPARTMENTS);
+    let mut comps_out = Compartments::new_copy(comps_in);
     if time > 0.0 {
         for i in 0..constants.compartments {
             comps_out.nitrogen[i] = calc_tissue_bottom(
                 comps_in.nitrogen[i],
                 time,
                 depth,
-                partial_water,
+                alveolar,
                 gas.f_n2,
                 constants.half_times_n2[i],
             );
@@ -237,7 +665,7 @@ fn calc_bottom(
                 comps_in.helium[i],
                 time,
                 depth,
-                partial_water,
+                alveolar,
                 gas.f_he,
                 constants.half_times_he[i],
             );
@@ -251,24 +679,23 @@ fn calc_tissue_change(
     time: f64,
     depth: Pressure,
     rate: DepthChange,
-    partial_water: f64,
+    alveolar: Alveolar,
     partial_pressure: f64,
     half_time: f64,
 ) -> f64 {
-    let bar = depth.to_bar();
     let rate_bar = rate.to_bar(); // rate of decent in bar
     let po = tissue_in;
-    let pio = (bar - (partial_water / 1000.0)) * partial_pressure;
+    let pio = alveolar.inspired(depth, partial_pressure);
     let r = rate_bar * partial_pressure;
-    let k = (2.0 as f64).ln() / half_time;
+    let k = 2.0_f64.ln() / half_time;
     pio + r * (time - (1.0 / k)) - (pio - po - (r / k)) * (-k * time).exp()
 }
 
 // rate_mbar should be negative on ascent
-fn calc_change(
+pub(crate) fn calc_change(
     comps_in: &Compartments,
     constants: &TissueConstants,
-    partial_water: f64,
+    alveolar: Alveolar,
     gas: Gas,
     rate: DepthChange,
     from_depth: Pressure,
@@ -282,7 +709,7 @@ fn calc_change(
             time,
             from_depth,
             rate,
-            partial_water,
+            alveolar,
             gas.f_n2,
             constants.half_times_n2[i],
         );
@@ -291,7 +718,7 @@ fn calc_change(
             time,
             from_depth,
             rate,
-            partial_water,
+            alveolar,
             gas.f_he,
             constants.half_times_he[i],
         );
@@ -301,23 +728,29 @@ fn calc_change(
 
 fn next_stop(dive: &Dive, comps: &Compartments, constants: &TissueConstants, gf: f64) -> Pressure // Depth of next stop.
 {
-    let stop = calc_ceiling(comps, dive.atm_pressure, constants, gf);
+    let stop = calc_ceiling(
+        comps,
+        dive.atm_pressure,
+        constants,
+        dive.coefficient_mix,
+        gf,
+    );
     if stop <= dive.atm_pressure {
         return dive.atm_pressure;
     }
-    if stop.to_mbar() <= dive.last_stop.to_mbar() {
+    if stop <= dive.last_stop {
         return dive.last_stop;
     }
-    let mut i = dive.last_stop.to_mbar() + dive.stop_size.to_mbar();
-    while stop.to_mbar() > i {
-        i += dive.stop_size.to_mbar();
+    let mut i = dive.last_stop + dive.stop_size;
+    while stop > i {
+        i += dive.stop_size;
     }
-    Pressure::millibar(i as f64)
+    i
 }
 
 // Intended to be a pure function and this is what it needs.
 #[allow(clippy::too_many_arguments)]
-fn change_depth(
+pub(crate) fn change_depth(
     dive: &Dive,
     constants: &TissueConstants,
     gasses: &[Gas],
@@ -326,24 +759,46 @@ fn change_depth(
     to_depth: Pressure,
     setpoint: f64,
     comps_in: &Compartments,
-) -> (Compartments, Segment) {
+) -> Result<(Compartments, Segment), String> {
+    let segment_type = if rate.to_mbar() < 0.0 {
+        SegmentType::UP
+    } else {
+        SegmentType::DOWN
+    };
+    let gas: Gas = find_gas(dive, gasses, to_depth, segment_type, setpoint)?;
+    change_depth_gas(
+        dive, constants, gas, rate, from_depth, to_depth, setpoint, comps_in,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn change_depth_gas(
+    dive: &Dive,
+    constants: &TissueConstants,
+    gas: Gas,
+    rate: DepthChange,
+    from_depth: Pressure,
+    to_depth: Pressure,
+    setpoint: f64,
+    comps_in: &Compartments,
+) -> Result<(Compartments, Segment), String> {
     let segment_type = if rate.to_mbar() < 0.0 {
         SegmentType::UP
     } else {
         SegmentType::DOWN
     };
     let time: f64 = (to_depth.to_mbar() - from_depth.to_mbar()) / rate.to_mbar();
-    let gas: Gas = find_gas(dive, gasses, to_depth, segment_type, setpoint);
     let comps_out = calc_change(
         comps_in,
         constants,
-        dive.partial_water,
+        dive.alveolar,
         gas,
         rate,
         from_depth,
         to_depth,
     );
-    let otu_cns = crate::otu_cns::descent(rate, from_depth, to_depth, gas);
+    let otu_cns = crate::otu_cns::descent(rate, from_depth, to_depth, gas, dive.cns_model)?;
+    let (end, density, warnings) = gas_limits(dive, gas, from_depth.max(to_depth));
 
     let segment = Segment {
         segment_type,
@@ -351,12 +806,117 @@ fn change_depth(
         raw_time: time,
         time: time.ceil() as u32,
         gas,
-        ceiling: 0,
+        ceiling: dive.atm_pressure,
+        leading_compartment: 0,
+        gf99: 0.0,
+        surface_gf: 0.0,
         otu_cns,
         setpoint,
         compartments: Compartments::new_copy(&comps_out),
+        end,
+        density,
+        warnings,
     };
-    (comps_out, segment)
+    Ok((comps_out, segment))
+}
+
+// Descends on the bottom gas, or on a travel gas until the switch depth (the
+// dive's travel_switch or the bottom gas's min depth) when the bottom gas is
+// not breathable at from_depth.
+#[allow(clippy::too_many_arguments)]
+fn descend(
+    dive: &Dive,
+    constants: &TissueConstants,
+    gasses: &[Gas],
+    from_depth: Pressure,
+    to_depth: Pressure,
+    setpoint: f64,
+    comps_in: &Compartments,
+) -> Result<(Compartments, Vec<Segment>), String> {
+    let gas = find_gas(dive, gasses, to_depth, SegmentType::DOWN, setpoint)?;
+    if let DiveType::CCR = dive.dive_type {
+        let (comps, seg) = change_depth_gas(
+            dive,
+            constants,
+            gas,
+            dive.descent_rate,
+            from_depth,
+            to_depth,
+            setpoint,
+            comps_in,
+        )?;
+        return Ok((comps, vec![seg]));
+    }
+    if gas.use_gas(from_depth, SegmentType::DOWN) {
+        let (comps, seg) = change_depth_gas(
+            dive,
+            constants,
+            gas,
+            dive.descent_rate,
+            from_depth,
+            to_depth,
+            setpoint,
+            comps_in,
+        )?;
+        return Ok((comps, vec![seg]));
+    }
+    let switch = match dive.travel_switch {
+        Some(depth) => Pressure::from_depth(depth, dive.atm_pressure, dive.water),
+        None => gas.min_pressure(),
+    };
+    let format = |p: Pressure| dive.format_depth(p.to_depth(dive.atm_pressure, dive.water));
+    if switch < gas.min_pressure() || switch <= from_depth || switch > to_depth {
+        return Err(format!(
+            "Travel gas switch at {} is outside the range of {} ({} to {}).",
+            format(switch),
+            gas,
+            format(gas.min_pressure().max(dive.atm_pressure)),
+            format(to_depth)
+        ));
+    }
+    let mut travel: Option<Gas> = None;
+    for g in gasses {
+        if g.use_gas(from_depth, SegmentType::DOWN)
+            && g.use_gas(switch, SegmentType::DOWN)
+            && travel.is_none_or(|t| g.f_o2 > t.f_o2)
+        {
+            travel = Some(*g);
+        }
+    }
+    let travel = travel.ok_or_else(|| {
+        let depth = |p: Pressure| {
+            p.to_depth(dive.atm_pressure, dive.water)
+                .format(dive.metric, 1)
+        };
+        format!(
+            "No travel gas from {} to {} where {} reaches ppO2 {:.2}.",
+            depth(from_depth.max(dive.atm_pressure)),
+            depth(switch),
+            gas,
+            gas.min_ppo2
+        )
+    })?;
+    let (comps, seg_travel) = change_depth_gas(
+        dive,
+        constants,
+        travel,
+        dive.descent_rate,
+        from_depth,
+        switch,
+        setpoint,
+        comps_in,
+    )?;
+    let (comps, seg) = change_depth_gas(
+        dive,
+        constants,
+        gas,
+        dive.descent_rate,
+        switch,
+        to_depth,
+        setpoint,
+        &comps,
+    )?;
+    Ok((comps, vec![seg_travel, seg]))
 }
 
 fn merge_ascends(prev_seg: Option<Segment>, new_seg: Segment) -> Vec<Segment> {
@@ -367,11 +927,15 @@ fn merge_ascends(prev_seg: Option<Segment>, new_seg: Segment) -> Vec<Segment> {
         if seg.segment_type == SegmentType::UP && seg.gas == new_seg.gas {
             time += seg.raw_time;
             otu_cns += seg.otu_cns;
+            // The previous segment started deeper so keep its END/density.
             let seg_out = Segment {
                 time: time.ceil() as u32,
                 raw_time: time,
                 otu_cns,
                 compartments: Compartments::new_copy(&new_seg.compartments),
+                end: seg.end,
+                density: seg.density,
+                warnings: seg.warnings,
                 ..new_seg
             };
             segs.push(seg_out);
@@ -392,13 +956,25 @@ fn bottom(
     time: f64,
     setpoint: f64,
     comps_in: &Compartments,
-) -> (Compartments, Segment) {
-    let gas = find_gas(dive, gasses, depth, SegmentType::LEVEL, setpoint);
-    let comps_out = calc_bottom(comps_in, constants, dive.partial_water, depth, time, gas);
-    let ceiling = calc_ceiling(comps_in, dive.atm_pressure, constants, dive.gf_lo);
-    let otu_cns = crate::otu_cns::bottom(depth, time, gas);
+) -> Result<(Compartments, Segment), String> {
+    let gas = find_gas(dive, gasses, depth, SegmentType::LEVEL, setpoint)?;
+    bottom_gas(dive, constants, gas, depth, time, setpoint, comps_in)
+}
+
+pub(crate) fn bottom_gas(
+    dive: &Dive,
+    constants: &TissueConstants,
+    gas: Gas,
+    depth: Pressure,
+    time: f64,
+    setpoint: f64,
+    comps_in: &Compartments,
+) -> Result<(Compartments, Segment), String> {
+    let comps_out = calc_bottom(comps_in, constants, dive.alveolar, depth, time, gas);
+    let otu_cns = crate::otu_cns::bottom(depth, time, gas, dive.cns_model)?;
+    let (end, density, warnings) = gas_limits(dive, gas, depth);
     let new_comps = Compartments::new_copy(&comps_out);
-    (
+    Ok((
         comps_out,
         Segment {
             segment_type: SegmentType::LEVEL,
@@ -406,12 +982,18 @@ fn bottom(
             raw_time: time,
             time: time.ceil() as u32,
             gas,
-            ceiling: ceiling.to_mbar() as i32,
+            ceiling: dive.atm_pressure,
+            leading_compartment: 0,
+            gf99: 0.0,
+            surface_gf: 0.0,
             otu_cns,
             setpoint,
             compartments: new_comps,
+            end,
+            density,
+            warnings,
         },
-    )
+    ))
 }
 
 fn calc_bottom_segment(
@@ -432,7 +1014,7 @@ fn calc_bottom_segment(
         comps_out = calc_bottom(
             &comps_out,
             constants,
-            dive.partial_water,
+            dive.alveolar,
             depth,
             segment_time,
             gas,
@@ -452,8 +1034,8 @@ fn calc_deco_int(
     gasses: &[Gas],
     last_depth_in: Pressure,
     gf: f64,
-    gf_slope: f64,
-) -> (Vec<Segment>, Compartments) {
+) -> Result<(Vec<Segment>, Compartments), String> {
+    let first_stop = last_depth_in;
     let mut segments: Vec<Segment> = Vec::new();
     let mut main_done = false;
     let mut ngf = gf;
@@ -472,7 +1054,7 @@ fn calc_deco_int(
                 fs,
                 dive.deco_setpoint,
                 &comps_out,
-            );
+            )?;
             let mut newsegs = merge_ascends(segments.pop(), seg);
             segments.append(&mut newsegs);
             comps_out = comps;
@@ -480,23 +1062,18 @@ fn calc_deco_int(
             let seg = &segments[segments.len() - 1];
             if seg.raw_time > 1.0 && seg.time as f64 > seg.raw_time {
                 let time_off = seg.time as f64 - seg.raw_time;
-                comps_out = calc_bottom(
-                    &comps_out,
-                    constants,
-                    dive.partial_water,
-                    fs,
-                    time_off,
-                    seg.gas,
-                );
+                comps_out =
+                    calc_bottom(&comps_out, constants, dive.alveolar, fs, time_off, seg.gas);
             }
         }
         if fs <= dive.atm_pressure {
-            return (segments, comps_out);
+            return Ok((segments, comps_out));
         } // At surface, done...
-        ngf = next_gf(gf_slope, dive, fs);
+        ngf = next_gf(dive, first_stop, fs);
         nfs = next_stop(dive, &comps_out, constants, ngf);
-        if nfs == fs {
-            let gas = find_gas(dive, gasses, fs, SegmentType::UP, dive.deco_setpoint);
+        // A schedule with a lower GF here may put the ceiling below fs, stay.
+        if nfs >= fs {
+            let gas = find_gas(dive, gasses, fs, SegmentType::UP, dive.deco_setpoint)?;
             // XXX I want to be a function...
             let time_off = if segments.is_empty() {
                 0.0
@@ -514,7 +1091,8 @@ fn calc_deco_int(
                 calc_bottom_segment(dive, &comps_out, constants, gas, fs, ngf, time_off);
             comps_out = new_comps;
             nfs = next_stop(dive, &comps_out, constants, ngf);
-            let otu_cns = crate::otu_cns::bottom(fs, time, gas);
+            let otu_cns = crate::otu_cns::bottom(fs, time, gas, dive.cns_model)?;
+            let (end, density, warnings) = gas_limits(dive, gas, fs);
             last_depth = fs;
             segments.push(Segment {
                 segment_type: SegmentType::LEVEL,
@@ -522,43 +1100,44 @@ fn calc_deco_int(
                 raw_time: time,
                 time: time.ceil() as u32,
                 gas,
-                ceiling: 0,
+                ceiling: dive.atm_pressure,
+                leading_compartment: 0,
+                gf99: 0.0,
+                surface_gf: 0.0,
                 otu_cns,
                 setpoint: dive.deco_setpoint,
                 compartments: Compartments::new_copy(&comps_out),
+                end,
+                density,
+                warnings,
             });
         }
         main_done = nfs <= dive.atm_pressure;
     }
-    (segments, comps_out)
+    Ok((segments, comps_out))
 }
 
-fn initial_segments(
+pub(crate) fn initial_segments(
     dive: &Dive,
     compartments: &Compartments,
     constants: &TissueConstants,
     segments_in: &[SegmentIn],
     gasses: &[Gas],
-) -> (Vec<Segment>, Compartments, Pressure) {
+) -> Result<(Vec<Segment>, Compartments, Pressure), String> {
     let mut comps_out = Compartments::new_copy(compartments);
     let mut segments: Vec<Segment> = Vec::new();
     let mut last_depth = dive.atm_pressure;
     for s in segments_in {
-        let depth = Pressure::from_depth(s.depth, dive.atm_pressure);
-        let raw_time: f64;
+        let depth = Pressure::from_depth(s.depth, dive.atm_pressure, dive.water);
+        let mut raw_time = 0.0;
         if last_depth < depth {
-            let (comps, seg) = change_depth(
-                dive,
-                constants,
-                gasses,
-                dive.descent_rate,
-                last_depth,
-                depth,
-                s.setpoint,
-                &comps_out,
-            );
-            raw_time = seg.raw_time;
-            segments.push(seg);
+            let (comps, segs) = descend(
+                dive, constants, gasses, last_depth, depth, s.setpoint, &comps_out,
+            )?;
+            for seg in segs {
+                raw_time += seg.raw_time;
+                segments.push(seg);
+            }
             comps_out = comps;
         } else {
             let (comps, seg) = change_depth(
@@ -570,7 +1149,7 @@ fn initial_segments(
                 depth,
                 s.setpoint,
                 &comps_out,
-            );
+            )?;
             raw_time = seg.raw_time;
             segments.push(seg);
             comps_out = comps;
@@ -583,7 +1162,7 @@ fn initial_segments(
             s.time - raw_time,
             s.setpoint,
             &comps_out,
-        );
+        )?;
         comps_out = comps;
         segments.push(Segment {
             time: (s.time - raw_time.ceil()) as u32,
@@ -591,7 +1170,51 @@ fn initial_segments(
         });
         last_depth = depth;
     }
-    (segments, comps_out, last_depth)
+    Ok((segments, comps_out, last_depth))
+}
+
+// Rounds depth to the nearest stop on the dive's stop grid.
+pub(crate) fn stop_grid(dive: &Dive, depth: Pressure) -> Pressure {
+    let steps = ((depth - dive.last_stop).to_mbar() / dive.stop_size.to_mbar()).round();
+    dive.last_stop + dive.stop_size * steps.max(0.0)
+}
+
+// Deep stops between depth and the first stop fs, deepest first.
+fn deep_stops(
+    dive: &Dive,
+    comps: &Compartments,
+    constants: &TissueConstants,
+    depth: Pressure,
+    fs: Pressure,
+) -> Vec<Pressure> {
+    let mut stops = Vec::new();
+    if fs <= dive.atm_pressure {
+        return stops;
+    }
+    match dive.deep_stops {
+        DeepStops::None => {}
+        // Half way between the depth and the first stop.
+        DeepStops::Pyle { .. } => {
+            let stop = stop_grid(dive, Pressure::millibar((depth + fs).to_mbar() / 2.0));
+            if stop > fs && stop < depth {
+                stops.push(stop);
+            }
+        }
+        // From ratio of the way from the first stop to where the leading
+        // tissue starts off gassing, every stop to the first stop.
+        DeepStops::Baker { ratio, .. } => {
+            let off_gas = tissue_ceiling(comps, constants, dive.coefficient_mix, 0.0).min(depth);
+            let mut stop = stop_grid(dive, fs + (off_gas - fs) * ratio);
+            while stop >= depth {
+                stop -= dive.stop_size;
+            }
+            while stop > fs {
+                stops.push(stop);
+                stop -= dive.stop_size;
+            }
+        }
+    }
+    stops
 }
 
 fn ascend_to_first_stop(
@@ -600,12 +1223,49 @@ fn ascend_to_first_stop(
     constants: &TissueConstants,
     gasses: &[Gas],
     depth: Pressure,
-) -> (Vec<Segment>, Compartments, Pressure) {
+) -> Result<(Vec<Segment>, Compartments, Pressure), String> {
     let mut last_depth = depth;
     let mut segments: Vec<Segment> = Vec::new();
     let mut comps_out = Compartments::new_copy(compartments);
-    let mut fs = next_stop(dive, &comps_out, constants, dive.gf_lo);
-    let mut at_first_stop = false;
+    let mut fs = next_stop(dive, &comps_out, constants, ascent_gf(dive, last_depth));
+    // Pyle stops are one at a time from the first stop of the current tissues,
+    // Baker's are all planned from the natural first stop.
+    let mut stops = deep_stops(dive, &comps_out, constants, last_depth, fs);
+    while !stops.is_empty() {
+        for stop in stops {
+            let (comps, seg) = change_depth(
+                dive,
+                constants,
+                gasses,
+                dive.ascent_rate,
+                last_depth,
+                stop,
+                dive.deco_setpoint,
+                &comps_out,
+            )?;
+            let mut newsegs = merge_ascends(segments.pop(), seg);
+            segments.append(&mut newsegs);
+            let gas = find_gas(dive, gasses, stop, SegmentType::UP, dive.deco_setpoint)?;
+            let (comps, seg) = bottom_gas(
+                dive,
+                constants,
+                gas,
+                stop,
+                dive.deep_stops.time(),
+                dive.deco_setpoint,
+                &comps,
+            )?;
+            segments.push(seg);
+            comps_out = comps;
+            last_depth = stop;
+        }
+        fs = next_stop(dive, &comps_out, constants, ascent_gf(dive, last_depth));
+        stops = match dive.deep_stops {
+            DeepStops::Pyle { .. } => deep_stops(dive, &comps_out, constants, last_depth, fs),
+            _ => Vec::new(),
+        };
+    }
+    let mut at_first_stop = fs >= last_depth;
     while !at_first_stop {
         let (comps, seg) = change_depth(
             dive,
@@ -616,7 +1276,7 @@ fn ascend_to_first_stop(
             fs,
             dive.deco_setpoint,
             &comps_out,
-        );
+        )?;
         comps_out = comps;
         let mut newsegs = merge_ascends(segments.pop(), seg);
         segments.append(&mut newsegs);
@@ -626,12 +1286,69 @@ fn ascend_to_first_stop(
         // if it has cleared in the ascent to it- leaving them in seems to match
         // Shearwater closer and not Subsurface...
         last_depth = fs;
-        fs = next_stop(dive, &comps_out, constants, dive.gf_lo);
+        fs = next_stop(dive, &comps_out, constants, ascent_gf(dive, last_depth));
         at_first_stop = fs >= last_depth;
     }
 
     last_depth = fs;
-    (segments, comps_out, last_depth)
+    Ok((segments, comps_out, last_depth))
+}
+
+/// Tissue state for a diver who arrived at the dive's altitude hours ago from
+/// from_altitude, starting at equilibrium there and breathing air since.
+pub fn acclimatized_compartments(
+    dive: &Dive,
+    constants: &TissueConstants,
+    from_altitude: f64,
+    hours: f64,
+) -> Compartments {
+    let comps = Compartments::new_altitude(from_altitude, dive.alveolar, constants.compartments);
+    calc_bottom(
+        &comps,
+        constants,
+        dive.alveolar,
+        dive.atm_pressure,
+        hours * 60.0,
+        Gas::new_bottom(0.21, 0.0, 1.4),
+    )
+}
+
+// Warns on the segment where the dive's cumulative CNS or OTU passes its limit.
+pub(crate) fn oxygen_warnings(dive: &Dive, segments: &mut [Segment]) {
+    let mut total = OtuCns {
+        otu: dive.start_otu,
+        cns: dive.start_cns,
+    };
+    for s in segments.iter_mut() {
+        let prev = total;
+        total += s.otu_cns;
+        if prev.cns <= dive.max_cns && total.cns > dive.max_cns {
+            s.warnings.push(format!(
+                "CNS {:.0}% exceeds {:.0}%.",
+                total.cns, dive.max_cns
+            ));
+        }
+        if prev.otu <= dive.max_otu && total.otu > dive.max_otu {
+            s.warnings
+                .push(format!("OTU {:.0} exceeds {:.0}.", total.otu, dive.max_otu));
+        }
+    }
+}
+
+pub(crate) fn check_compartments(
+    compartments: &Compartments,
+    constants: &TissueConstants,
+) -> Result<(), String> {
+    if compartments.nitrogen.len() != constants.compartments
+        || compartments.helium.len() != constants.compartments
+    {
+        return Err(format!(
+            "Tissues have {} compartments, constants have {}.",
+            compartments.nitrogen.len(),
+            constants.compartments
+        ));
+    }
+    Ok(())
 }
 
 pub fn calc_deco(
@@ -640,24 +1357,40 @@ pub fn calc_deco(
     constants: &TissueConstants,
     segments_in: &[SegmentIn],
     gasses: &[Gas],
-) -> Result<Vec<Segment>, String> {
+) -> Result<DivePlan, String> {
     if segments_in.is_empty() {
         return Err("Must provide segment(s) to calculate deco against.".to_string());
     }
+    check_compartments(compartments, constants)?;
     let (mut segments, comps_out, last_depth) =
-        initial_segments(dive, compartments, constants, segments_in, gasses);
+        initial_segments(dive, compartments, constants, segments_in, gasses)?;
+    let bottom_segments = segments.len();
     // Ascend to the first stop.
     let (mut newsegs, comps_out, last_depth) =
-        ascend_to_first_stop(dive, &comps_out, constants, gasses, last_depth);
+        ascend_to_first_stop(dive, &comps_out, constants, gasses, last_depth)?;
     segments.append(&mut newsegs);
-    let gf_slope =
-        (dive.gf_hi - dive.gf_lo) / -((last_depth.to_mbar() - dive.atm_pressure.to_mbar()) as f64);
-    let gf = next_gf(gf_slope, dive, last_depth);
-    let (mut newsegs, _comps_out) = calc_deco_int(
-        dive, &comps_out, constants, gasses, last_depth, gf, gf_slope,
-    );
+    for seg in segments.iter_mut() {
+        let gf = ascent_gf(dive, seg.depth);
+        tissue_state(dive, constants, seg, gf);
+    }
+    let first_deco = segments.len();
+    let gf = next_gf(dive, last_depth, last_depth);
+    let (mut newsegs, comps_out) =
+        calc_deco_int(dive, &comps_out, constants, gasses, last_depth, gf)?;
     segments.append(&mut newsegs);
-    Ok(segments)
+    // Stops use the GF for their depth, ascents the GF of the stop they left.
+    let mut from = last_depth;
+    for seg in segments[first_deco..].iter_mut() {
+        let depth = if seg.segment_type == SegmentType::LEVEL {
+            seg.depth
+        } else {
+            from
+        };
+        tissue_state(dive, constants, seg, next_gf(dive, last_depth, depth));
+        from = seg.depth;
+    }
+    oxygen_warnings(dive, &mut segments);
+    Ok(DivePlan::new(dive, segments, bottom_segments, comps_out))
 }
 
 pub fn calc_deco_a(
@@ -665,7 +1398,7 @@ pub fn calc_deco_a(
     compartments: &Compartments,
     segments: &[SegmentIn],
     gasses: &[Gas],
-) -> Result<Vec<Segment>, String> {
+) -> Result<DivePlan, String> {
     calc_deco(dive, compartments, &CONSTANTS_A, segments, gasses)
 }
 pub fn calc_deco_b(
@@ -673,7 +1406,7 @@ pub fn calc_deco_b(
     compartments: &Compartments,
     segments: &[SegmentIn],
     gasses: &[Gas],
-) -> Result<Vec<Segment>, String> {
+) -> Result<DivePlan, String> {
     calc_deco(dive, compartments, &CONSTANTS_B, segments, gasses)
 }
 pub fn calc_deco_c(
@@ -681,6 +1414,351 @@ pub fn calc_deco_c(
     compartments: &Compartments,
     segments: &[SegmentIn],
     gasses: &[Gas],
-) -> Result<Vec<Segment>, String> {
+) -> Result<DivePlan, String> {
     calc_deco(dive, compartments, &CONSTANTS_C, segments, gasses)
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn altitude() {
+        let dive = Dive::default().with_altitude(2000.0);
+        assert!((dive.atm_pressure.to_mbar() - 795.0).abs() < 1.0);
+
+        let fresh = acclimatized_compartments(&dive, &CONSTANTS_C, 0.0, 0.0);
+        let settled = acclimatized_compartments(&dive, &CONSTANTS_C, 0.0, 72.0);
+        let local = Compartments::new_altitude(2000.0, dive.alveolar, COMPARTMENTS);
+        for i in 0..COMPARTMENTS {
+            assert!(fresh.nitrogen[i] > settled.nitrogen[i]);
+            assert!((settled.nitrogen[i] - local.nitrogen[i]).abs() < 0.01);
+        }
+
+        // Air and nitrox are breathable from the surface of a mountain lake.
+        let segments = vec![SegmentIn::new_bottom(Depth::meters(30.0), 20.0, 1.4)];
+        let sea_level = Compartments::new_surface(1013.0, dive.alveolar, COMPARTMENTS);
+        let at_sea = calc_deco_c(
+            &Dive::default(),
+            &sea_level,
+            &segments,
+            &[Gas::new_bottom(0.21, 0.0, 1.4)],
+        )
+        .unwrap();
+        for altitude in [1500.0, 2000.0, 2500.0] {
+            let dive = Dive::default().with_altitude(altitude);
+            let comps = Compartments::new_altitude(altitude, dive.alveolar, COMPARTMENTS);
+            for gasses in [
+                vec![Gas::new_bottom(0.21, 0.0, 1.4)],
+                vec![Gas::new_bottom(0.32, 0.0, 1.4), Gas::new_deco(0.5, 0.0)],
+            ] {
+                let plan = calc_deco_c(&dive, &comps, &segments, &gasses).unwrap();
+                assert!(plan.segments[0].gas == gasses[0]);
+                if gasses.len() == 1 {
+                    assert!(plan.run_time > at_sea.run_time);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn imperial_stops() {
+        let dive = Dive::default()
+            .with_altitude(1000.0)
+            .with_imperial()
+            .with_last_stop(Depth::feet(20.0));
+        let gasses = vec![Gas::new_bottom(0.21, 0.0, 1.4), Gas::new_deco(0.5, 0.0)];
+        let segments = vec![SegmentIn::new_bottom(Depth::feet(130.0), 30.0, 1.4)];
+        let segs = calc_deco_c(
+            &dive,
+            &Compartments::new_surface(1013.0, Alveolar::default(), COMPARTMENTS),
+            &segments,
+            &gasses,
+        )
+        .unwrap()
+        .segments;
+        let last = &segs[segs.len() - 1];
+        assert_eq!(
+            "20ft",
+            dive.format_depth(last.depth.to_depth(dive.atm_pressure, dive.water))
+        );
+        for s in segs.iter().filter(|s| s.segment_type == SegmentType::LEVEL) {
+            let feet = s.depth.to_depth(dive.atm_pressure, dive.water).to_feet();
+            assert!((feet / 10.0 - (feet / 10.0).round()).abs() < 0.001);
+        }
+        assert_eq!("30ft/min", dive.format_rate(dive.ascent_rate));
+        assert_eq!("3000psi", dive.format_pressure(Pressure::psi(3000.0)));
+        assert_eq!("80.0cuft", dive.format_volume(Volume::cubic_feet(80.0)));
+        let other_way = Dive::default().with_imperial().with_altitude(1000.0);
+        let dive = Dive::default().with_altitude(1000.0).with_imperial();
+        assert!((other_way.last_stop.to_mbar() - dive.last_stop.to_mbar()).abs() < 1e-9);
+        assert!(other_way.stop_size == dive.stop_size);
+    }
+
+    #[test]
+    fn travel_gas() {
+        let dive: Dive = Default::default();
+        let comps = Compartments::new_surface(1013.0, Alveolar::default(), COMPARTMENTS);
+        let segments = vec![SegmentIn::new_bottom(Depth::meters(90.0), 20.0, 1.4)];
+        let mut gasses = vec![
+            Gas::new_bottom(0.12, 0.65, 1.3),
+            Gas::new_deco(0.5, 0.0),
+            Gas::new_deco(1.0, 0.0),
+        ];
+        assert!(calc_deco_c(&dive, &comps, &segments, &gasses).is_err());
+
+        gasses.push(Gas::new_travel(0.21, 0.35, 1.4));
+        let segs = calc_deco_c(&dive, &comps, &segments, &gasses)
+            .unwrap()
+            .segments;
+        assert_eq!("21/35", format!("{}", segs[0].gas));
+        assert_eq!("12/65", format!("{}", segs[1].gas));
+        assert_eq!("12/65", format!("{}", segs[2].gas));
+        let switch = segs[0].depth.to_depth(dive.atm_pressure, dive.water);
+        assert_eq!(5.0, switch.to_meters().round());
+
+        let dive = Dive {
+            travel_switch: Some(Depth::meters(30.0)),
+            ..Default::default()
+        };
+        let segs = calc_deco_c(&dive, &comps, &segments, &gasses)
+            .unwrap()
+            .segments;
+        let switch = segs[0].depth.to_depth(dive.atm_pressure, dive.water);
+        assert_eq!(30.0, switch.to_meters().round());
+        assert_eq!("12/65", format!("{}", segs[2].gas));
+    }
+
+    #[test]
+    fn segment_tissue_state() {
+        let dive: Dive = Default::default();
+        let comps = Compartments::new_surface(1013.0, Alveolar::default(), COMPARTMENTS);
+        let segments = vec![SegmentIn::new_bottom(Depth::meters(50.0), 30.0, 1.4)];
+        let gasses = vec![Gas::new_bottom(0.21, 0.35, 1.4), Gas::new_deco(0.5, 0.0)];
+        let plan = calc_deco_c(&dive, &comps, &segments, &gasses).unwrap();
+        for s in &plan.segments {
+            assert!(s.ceiling <= s.depth);
+            assert!(s.gf99 <= s.surface_gf);
+        }
+        // Fast tissues lead at the end of the bottom time.
+        assert!(plan.segments[1].ceiling > dive.atm_pressure);
+        assert!(plan.segments[1].leading_compartment < 6);
+        let last = &plan.segments[plan.segments.len() - 1];
+        assert!(last.surface_gf > 0.0 && last.surface_gf <= dive.gf_hi * 100.0);
+    }
+
+    #[test]
+    fn custom_constants() {
+        // ZH-L16C with the 1b (5 minute) first compartment.
+        let mut half_times_n2 = HALF_TIMES_N2.to_vec();
+        let mut half_times_he = HALF_TIMES_HE.to_vec();
+        let mut n2_a_s = N2AS_C.to_vec();
+        let mut n2_b_s = N2BS.to_vec();
+        let mut he_a_s = HE_AS.to_vec();
+        let mut he_b_s = HE_BS.to_vec();
+        half_times_n2[0] = 5.0;
+        n2_a_s[0] = 1.1696;
+        n2_b_s[0] = 0.5578;
+        half_times_he[0] = 1.88;
+        he_a_s[0] = 1.6189;
+        he_b_s[0] = 0.4770;
+        let constants = TissueConstants::new(
+            half_times_n2.clone(),
+            half_times_he.clone(),
+            n2_a_s.clone(),
+            n2_b_s.clone(),
+            he_a_s.clone(),
+            he_b_s.clone(),
+        )
+        .unwrap();
+        let dive: Dive = Default::default();
+        let segments = vec![SegmentIn::new_bottom(Depth::meters(40.0), 30.0, 1.4)];
+        let gasses = vec![Gas::new_bottom(0.21, 0.0, 1.4)];
+        let comps = Compartments::new_surface(1013.0, Alveolar::default(), COMPARTMENTS);
+        assert!(calc_deco(&dive, &comps, &constants, &segments, &gasses).is_ok());
+
+        // Only the first 12 compartments.
+        let constants = TissueConstants::new(
+            half_times_n2[..12].to_vec(),
+            half_times_he[..12].to_vec(),
+            n2_a_s[..12].to_vec(),
+            n2_b_s[..12].to_vec(),
+            he_a_s[..12].to_vec(),
+            he_b_s[..12].to_vec(),
+        )
+        .unwrap();
+        assert!(calc_deco(&dive, &comps, &constants, &segments, &gasses).is_err());
+        let comps = Compartments::new_surface(1013.0, Alveolar::default(), 12);
+        let plan = calc_deco(&dive, &comps, &constants, &segments, &gasses).unwrap();
+        assert_eq!(12, plan.compartments.nitrogen.len());
+
+        assert!(TissueConstants::new(
+            half_times_n2,
+            half_times_he,
+            n2_a_s,
+            n2_b_s[..12].to_vec(),
+            he_a_s,
+            he_b_s,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn derived_constants() {
+        let constants =
+            TissueConstants::from_half_times(HALF_TIMES_N2.to_vec(), HALF_TIMES_HE.to_vec())
+                .unwrap();
+        assert_eq!(1.2599, (constants.n2_a_s[0] * 10000.0).round() / 10000.0);
+        assert_eq!(0.505, (constants.n2_b_s[0] * 10000.0).round() / 10000.0);
+
+        let surface = Pressure::millibar(1013.25);
+        let (m0, delta_m) = buhlmann_to_workman(N2AS_C[4], N2BS[4], surface);
+        let (a, b) = workman_to_buhlmann(m0, delta_m, surface);
+        assert!((a - N2AS_C[4]).abs() < 1e-9 && (b - N2BS[4]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn coefficient_mix() {
+        let empty = Compartments::new_empty(COMPARTMENTS);
+        let ceiling = tissue_ceiling(&empty, &CONSTANTS_C, CoefficientMix::Weighted, 0.5);
+        assert_eq!(0.0, ceiling.to_mbar());
+
+        let comps = Compartments::new_surface(1013.0, Alveolar::default(), COMPARTMENTS);
+        let segments = vec![SegmentIn::new_bottom(Depth::meters(60.0), 25.0, 1.4)];
+        let gasses = vec![Gas::new_bottom(0.18, 0.45, 1.4), Gas::new_deco(0.5, 0.0)];
+        let run_time = |mix| {
+            let dive = Dive {
+                coefficient_mix: mix,
+                ..Default::default()
+            };
+            calc_deco_c(&dive, &comps, &segments, &gasses)
+                .unwrap()
+                .run_time
+        };
+        let weighted = run_time(CoefficientMix::Weighted);
+        assert!(run_time(CoefficientMix::WorstOf) >= weighted);
+
+        // 3 bar N2 and 1 bar He in the first compartment: a weighted by
+        // pressure, b by pressure over b for Baker.
+        let mut loaded = Compartments::new_empty(COMPARTMENTS);
+        loaded.nitrogen[0] = 3.0;
+        loaded.helium[0] = 1.0;
+        let ab = |comps: &Compartments, mix| super::coefficients(comps, &CONSTANTS_C, mix, 0);
+        let (a, b) = ab(&loaded, CoefficientMix::Baker);
+        assert!((a - 1.380525).abs() < 1e-9);
+        assert!((b - 0.4821423).abs() < 1e-7);
+        let (a, b) = ab(&loaded, CoefficientMix::Weighted);
+        assert!((a - 1.380525).abs() < 1e-9 && (b - 0.484875).abs() < 1e-9);
+        // Helium only uses the helium coefficients on every mix.
+        loaded.nitrogen[0] = 0.0;
+        for mix in [
+            CoefficientMix::Weighted,
+            CoefficientMix::Baker,
+            CoefficientMix::WorstOf,
+        ] {
+            assert_eq!((HE_AS[0], HE_BS[0]), ab(&loaded, mix));
+        }
+    }
+
+    #[test]
+    fn gf_schedules() {
+        let comps = Compartments::new_surface(1013.0, Alveolar::default(), COMPARTMENTS);
+        let segments = vec![SegmentIn::new_bottom(Depth::meters(45.0), 30.0, 1.4)];
+        let gasses = vec![Gas::new_bottom(0.21, 0.35, 1.4), Gas::new_deco(0.5, 0.0)];
+        let plan = |dive: Dive| calc_deco_c(&dive, &comps, &segments, &gasses).unwrap();
+        let linear = plan(Default::default());
+        let surfacing = plan(Dive {
+            gf_surface: Some(0.95),
+            ..Default::default()
+        });
+        assert!(surfacing.run_time < linear.run_time);
+        // A higher GF at the natural first stop clears it on the way up.
+        let anchored = plan(Dive {
+            gf_schedule: GfSchedule::Anchored(Depth::meters(45.0)),
+            ..Default::default()
+        });
+        assert!(anchored.segments[2].depth < linear.segments[2].depth);
+        // Stops deeper than the table use gf_lo.
+        let strict = plan(Dive {
+            gf_schedule: GfSchedule::Table(GfTable::new(&[(Depth::meters(3.0), 0.3)]).unwrap()),
+            ..Default::default()
+        });
+        assert!(strict.run_time > linear.run_time);
+    }
+
+    #[test]
+    fn deep_stops() {
+        let comps = Compartments::new_surface(1013.0, Alveolar::default(), COMPARTMENTS);
+        let segments = vec![SegmentIn::new_bottom(Depth::meters(60.0), 25.0, 1.4)];
+        let gasses = vec![Gas::new_bottom(0.18, 0.45, 1.4), Gas::new_deco(0.5, 0.0)];
+        let plan = |deep_stops| {
+            let dive = Dive {
+                deep_stops,
+                ..Default::default()
+            };
+            calc_deco_c(&dive, &comps, &segments, &gasses).unwrap()
+        };
+        let stops = |p: &DivePlan| -> Vec<(f64, u32)> {
+            p.segments[2..]
+                .iter()
+                .filter(|s| s.segment_type == SegmentType::LEVEL)
+                .map(|s| (s.depth.to_mbar(), s.time))
+                .collect()
+        };
+        let none = plan(DeepStops::None);
+        let first = stops(&none)[0].0;
+
+        let pyle = stops(&plan(DeepStops::Pyle { time: 2.0 }));
+        assert!(pyle[0].0 > first && pyle[0].0 < 7000.0);
+        assert_eq!(2, pyle[0].1);
+
+        let baker = stops(&plan(DeepStops::Baker {
+            ratio: 0.5,
+            time: 1.0,
+        }));
+        assert!(baker.len() > stops(&none).len());
+        assert!(baker[0].0 > first && baker[0].1 == 1);
+    }
+
+    #[test]
+    fn water() {
+        let dive = Dive::default().with_water(Water::Salt).unwrap();
+        let meters = |p: Pressure| p.to_depth(dive.atm_pressure, dive.water).to_meters();
+        assert!((meters(dive.last_stop) - 3.0).abs() < 1e-9);
+        let stop = Depth::from_pressure(dive.stop_size, Pressure::millibar(0.0), dive.water);
+        assert!((stop.to_meters() - 3.0).abs() < 1e-9);
+        assert!((dive.ascent_rate.to_depth(dive.water).to_meters() + 10.0).abs() < 1e-9);
+        assert!((dive.descent_rate.to_depth(dive.water).to_meters() - 18.0).abs() < 1e-9);
+        assert!(Dive::default().with_water(Water::Custom(0.0)).is_err());
+    }
+
+    #[test]
+    fn repetitive_oxygen() {
+        let comps = Compartments::new_surface(1013.0, Alveolar::default(), COMPARTMENTS);
+        let segments = vec![SegmentIn::new_bottom(Depth::meters(33.0), 40.0, 1.4)];
+        let gasses = vec![Gas::new_bottom(0.32, 0.0, 1.4)];
+        let cns_warnings = |plan: &DivePlan| {
+            plan.segments
+                .iter()
+                .flat_map(|s| s.warnings.iter())
+                .filter(|w| w.starts_with("CNS"))
+                .count()
+        };
+        let dive = Dive::default();
+        let first = calc_deco_c(&dive, &comps, &segments, &gasses).unwrap();
+        // A limit the dive stays under on its own.
+        let dive = Dive {
+            max_cns: first.otu_cns.cns * 1.2,
+            ..dive
+        };
+        assert_eq!(0, cns_warnings(&first));
+
+        let mut exposure = OxygenExposure::new();
+        exposure.add_dive(&dive, &first.segments);
+        exposure.surface_interval(60.0);
+        let dive = dive.with_exposure(&exposure);
+        assert!(dive.start_otu == first.otu_cns.otu);
+        let second = calc_deco_c(&dive, &comps, &segments, &gasses).unwrap();
+        assert_eq!(1, cns_warnings(&second));
+    }
+}
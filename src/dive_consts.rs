@@ -13,10 +13,6 @@ pub const N2AS_C: [f64; COMPARTMENTS] = [
     0.2835, 0.2610, 0.2480, 0.2327,
 ];
 
-//pub const PARTIAL_WATER: f64 = 056.7;
-pub const PARTIAL_WATER: f64 = 062.7;
-//pub const PARTIAL_WATER: f64 = 049.3;
-
 pub const HALF_TIMES_N2: [f64; COMPARTMENTS] = [
     4.00, 8.00, 12.50, 18.50, 27.00, 38.30, 54.30, 77.00, 109.00, 146.00, 187.00, 239.00, 305.00,
     390.00, 498.00, 635.00,
@@ -37,3 +33,8 @@ pub const N2BS: [f64; COMPARTMENTS] = [
     0.5050, 0.6514, 0.7222, 0.7825, 0.8126, 0.8434, 0.8693, 0.8910, 0.9092, 0.9222, 0.9319, 0.9403,
     0.9477, 0.9544, 0.9602, 0.9653,
 ];
+
+// Gas densities in g/L at 1 atm (0°C).
+pub const DENSITY_O2: f64 = 1.429;
+pub const DENSITY_N2: f64 = 1.251;
+pub const DENSITY_HE: f64 = 0.1786;
@@ -0,0 +1,141 @@
+use crate::dive::*;
+use crate::gas::Gas;
+use crate::otu_cns::OtuCns;
+use crate::segment::*;
+use crate::segment_type::SegmentType;
+use crate::types::*;
+
+/// Result of planning a dive with totals computed once for every client.
+pub struct DivePlan {
+    pub segments: Vec<Segment>,
+    pub bottom_segments: usize, // segments before the ascent
+    pub run_times: Vec<u32>,    // run time at the end of each segment
+    pub run_time: u32,
+    pub tts: u32,       // time to surface, from leaving the bottom
+    pub deco_time: u32, // time at stops
+    pub max_depth: Depth,
+    pub average_depth: Depth,
+    pub otu_cns: OtuCns,
+    pub gas_usage: Vec<(Gas, Volume)>, // surface volume per gas, OC only
+    pub compartments: Compartments,    // tissues at the end of the dive
+}
+
+impl DivePlan {
+    // bottom_segments is the number of segments before the ascent started.
+    pub fn new(
+        dive: &Dive,
+        segments: Vec<Segment>,
+        bottom_segments: usize,
+        compartments: Compartments,
+    ) -> DivePlan {
+        let mut run_times = Vec::with_capacity(segments.len());
+        let mut run_time = 0;
+        let mut tts = 0;
+        let mut deco_time = 0;
+        let mut max_depth = dive.atm_pressure;
+        let mut depth_time = 0.0;
+        let mut total_time = 0.0;
+        let mut otu_cns = OtuCns { otu: 0.0, cns: 0.0 };
+        let mut gas_usage: Vec<(Gas, Volume)> = Vec::new();
+        let mut last_depth = dive.atm_pressure;
+        for (i, s) in segments.iter().enumerate() {
+            run_time += s.time;
+            run_times.push(run_time);
+            if i >= bottom_segments {
+                tts += s.time;
+                if s.segment_type == SegmentType::LEVEL {
+                    deco_time += s.time;
+                }
+            }
+            max_depth = max_depth.max(s.depth);
+            let avg = if s.segment_type == SegmentType::LEVEL {
+                s.depth
+            } else {
+                (last_depth + s.depth) * 0.5
+            };
+            depth_time += (avg - dive.atm_pressure).to_mbar() * s.raw_time;
+            total_time += s.raw_time;
+            otu_cns += s.otu_cns;
+            if let DiveType::OC = dive.dive_type {
+                let sac = if i < bottom_segments {
+                    dive.sac_bottom
+                } else {
+                    dive.sac_deco
+                };
+                let used = sac * (s.raw_time * avg.to_bar());
+                match gas_usage.iter_mut().find(|(g, _)| *g == s.gas) {
+                    Some((_, volume)) => *volume += used,
+                    None => gas_usage.push((s.gas, used)),
+                }
+            }
+            last_depth = s.depth;
+        }
+        let average = if total_time > 0.0 {
+            Pressure::millibar(depth_time / total_time)
+        } else {
+            Pressure::millibar(0.0)
+        };
+        DivePlan {
+            segments,
+            bottom_segments,
+            run_times,
+            run_time,
+            tts,
+            deco_time,
+            max_depth: max_depth.to_depth(dive.atm_pressure, dive.water),
+            average_depth: Depth::from_pressure(average, Pressure::millibar(0.0), dive.water),
+            otu_cns,
+            gas_usage,
+            compartments,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn totals() {
+        let dive: Dive = Default::default();
+        let comps = Compartments::new_surface(1013.0, Alveolar::default(), COMPARTMENTS);
+        let air = Gas::new_bottom(0.21, 0.0, 1.4);
+        let segments = vec![SegmentIn::new_bottom(Depth::meters(30.0), 30.0, 1.4)];
+        let plan = calc_deco_c(&dive, &comps, &segments, &[air]).unwrap();
+        let times: Vec<u32> = plan.segments.iter().map(|s| s.time).collect();
+        let run_times: Vec<u32> = times
+            .iter()
+            .scan(0, |t, s| {
+                *t += s;
+                Some(*t)
+            })
+            .collect();
+        assert_eq!(run_times, plan.run_times);
+        assert_eq!(times.iter().sum::<u32>(), plan.run_time);
+        assert_eq!(30, plan.run_times[plan.bottom_segments - 1]);
+        // 30m/30min on air: stops of 2, 5 and 11 minutes at 9, 6 and 3m.
+        assert_eq!(51, plan.run_time);
+        assert_eq!(21, plan.tts);
+        assert_eq!(18, plan.deco_time);
+        assert!((plan.max_depth.to_meters() - 30.0).abs() < 1e-9);
+
+        // Descent and ascent at their mid depth, raw times.
+        let bar = |m: f64| {
+            (1013.0 + Pressure::from_depth_rel(Depth::meters(m), dive.water).to_mbar()) / 1000.0
+        };
+        let descent = 30.0 / 18.0;
+        let bottom = 20.0 * (descent * bar(15.0) + (30.0 - descent) * bar(30.0));
+        let deco = 15.0 * (2.1 * bar(19.5) + 2.0 * bar(9.0) + 5.0 * bar(6.0) + 11.0 * bar(3.0));
+        assert_eq!(1, plan.gas_usage.len());
+        assert!((plan.gas_usage[0].1.to_liters() - (bottom + deco)).abs() < 1e-6);
+
+        let depth_time = descent * 15.0
+            + (30.0 - descent) * 30.0
+            + 2.1 * 19.5
+            + 2.0 * 9.0
+            + 5.0 * 6.0
+            + 11.0 * 3.0;
+        let average = depth_time / (30.0 + 2.1 + 18.0);
+        assert!((plan.average_depth.to_meters() - average).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::dive::*;
+use crate::gas::Gas;
+use crate::segment::Compartments;
+use crate::types::*;
+
+// Longest surface interval (minutes) to search for a safe journey start.
+pub const MAX_SURFACE_WAIT: f64 = 48.0 * 60.0;
+
+pub struct AltitudeExposure {
+    pub exceeded: bool,
+    pub first_exceeded: Option<f64>, // minutes into the journey
+    pub max_overpressure: Pressure,  // largest ceiling above ambient (ceiling - ambient)
+    pub wait: Option<f64>,           // minutes at the dive's surface before the journey is safe
+}
+
+// Runs the journey, returns when (if ever) the ceiling went above ambient and
+// the worst ceiling - ambient seen.
+fn run_journey(
+    dive: &Dive,
+    constants: &TissueConstants,
+    comps_in: &Compartments,
+    profile: &[(f64, Pressure)],
+    gf: f64,
+) -> (Option<f64>, f64) {
+    let air = Gas::new_bottom(0.21, 0.0, 1.4);
+    let mut comps = Compartments::new_copy(comps_in);
+    let mut first_exceeded = None;
+    let mut max_over = tissue_ceiling(&comps, constants, dive.coefficient_mix, gf).to_mbar()
+        - dive.atm_pressure.to_mbar();
+    if max_over > 0.0 {
+        first_exceeded = Some(0.0);
+    }
+    let mut last_time = 0.0;
+    let mut last_pressure = dive.atm_pressure;
+    for (time, pressure) in profile {
+        let duration = time - last_time;
+        if duration <= 0.0 {
+            last_pressure = *pressure;
+            continue;
+        }
+        let steps = duration.ceil();
+        let step = duration / steps;
+        let rate = (pressure.to_mbar() - last_pressure.to_mbar()) / duration;
+        for i in 0..steps as usize {
+            let from = Pressure::millibar(last_pressure.to_mbar() + rate * step * i as f64);
+            let to = Pressure::millibar(from.to_mbar() + rate * step);
+            comps = if rate == 0.0 {
+                calc_bottom(&comps, constants, dive.alveolar, from, step, air)
+            } else {
+                calc_change(
+                    &comps,
+                    constants,
+                    dive.alveolar,
+                    air,
+                    DepthChange::descent_pressure(Pressure::millibar(rate)),
+                    from,
+                    to,
+                )
+            };
+            let over = tissue_ceiling(&comps, constants, dive.coefficient_mix, gf).to_mbar()
+                - to.to_mbar();
+            if over > 0.0 && first_exceeded.is_none() {
+                first_exceeded = Some(last_time + step * (i + 1) as f64);
+            }
+            max_over = max_over.max(over);
+        }
+        last_time = *time;
+        last_pressure = *pressure;
+    }
+    (first_exceeded, max_over)
+}
+
+/// Applies a post-dive ambient pressure profile of (minutes since surfacing,
+/// ambient pressure) points to the end-of-dive tissues, pressure changing linearly
+/// between points from the dive's surface pressure at time 0.  Reports whether the
+/// ceiling at gf ever goes above ambient and how long to wait at the surface
+/// before starting the journey so that it does not.
+pub fn altitude_exposure(
+    dive: &Dive,
+    constants: &TissueConstants,
+    compartments: &Compartments,
+    profile: &[(f64, Pressure)],
+    gf: f64,
+) -> Result<AltitudeExposure, String> {
+    check_compartments(compartments, constants)?;
+    let (first_exceeded, max_over) = run_journey(dive, constants, compartments, profile, gf);
+    let mut wait = None;
+    if first_exceeded.is_none() {
+        wait = Some(0.0);
+    } else {
+        let air = Gas::new_bottom(0.21, 0.0, 1.4);
+        let mut comps = Compartments::new_copy(compartments);
+        let mut waited = 0.0;
+        while waited < MAX_SURFACE_WAIT {
+            comps = calc_bottom(
+                &comps,
+                constants,
+                dive.alveolar,
+                dive.atm_pressure,
+                1.0,
+                air,
+            );
+            waited += 1.0;
+            if run_journey(dive, constants, &comps, profile, gf)
+                .0
+                .is_none()
+            {
+                wait = Some(waited);
+                break;
+            }
+        }
+    }
+    Ok(AltitudeExposure {
+        exceeded: first_exceeded.is_some(),
+        first_exceeded,
+        max_overpressure: Pressure::millibar(max_over),
+        wait,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn mountain_pass() {
+        let dive: Dive = Default::default();
+        let gasses = vec![Gas::new_bottom(0.21, 0.0, 1.4)];
+        let segments = vec![SegmentIn::new_bottom(Depth::meters(30.0), 40.0, 1.4)];
+        let plan = calc_deco_c(
+            &dive,
+            &Compartments::new_surface(1013.0, Alveolar::default(), COMPARTMENTS),
+            &segments,
+            &gasses,
+        )
+        .unwrap();
+        let comps = &plan.compartments;
+        // Drive up to a 2500m pass over half an hour and stay there for an hour.
+        let profile = vec![
+            (30.0, Pressure::from_altitude(2500.0)),
+            (90.0, Pressure::from_altitude(2500.0)),
+        ];
+        let exposure = altitude_exposure(&dive, &CONSTANTS_C, comps, &profile, dive.gf_hi).unwrap();
+        assert!(exposure.exceeded);
+        let wait = exposure.wait.unwrap();
+        assert!(wait > 0.0);
+
+        let flat = vec![(60.0, dive.atm_pressure)];
+        let exposure = altitude_exposure(&dive, &CONSTANTS_C, comps, &flat, dive.gf_hi).unwrap();
+        assert!(!exposure.exceeded);
+        assert_eq!(Some(0.0), exposure.wait);
+
+        let short = Compartments::new_surface(1013.0, Alveolar::default(), 12);
+        assert!(altitude_exposure(&dive, &CONSTANTS_C, &short, &profile, dive.gf_hi).is_err());
+    }
+}
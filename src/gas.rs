@@ -1,9 +1,19 @@
 use std::cmp::Ordering;
 use std::fmt;
+use std::str::FromStr;
 
+use crate::dive_consts::*;
 use crate::segment_type::SegmentType;
 use crate::types::*;
 
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum GasRole {
+    Bottom,
+    Deco,
+    Diluent,
+    Travel,
+}
+
 #[derive(Copy, Clone)]
 pub struct Gas {
     pub f_o2: f64,
@@ -16,25 +26,17 @@ pub struct Gas {
     pub use_ascent: bool,
     pub use_descent: bool,
     pub use_diluent: bool,
+    pub role: GasRole,
 }
 
 //pub const AIR: Gas = Gas::new_bottom(0.21, 0.0, 1.4);
 
 impl Gas {
-    fn new(
-        f_o2: f64,
-        f_he: f64,
-        ppo2: f64,
-        min_ppo2: f64,
-        use_ascent: bool,
-        use_descent: bool,
-        use_diluent: bool,
-    ) -> Gas {
-        let min = Pressure::bar(if f_o2 >= min_ppo2 {
-            1.0
-        } else {
-            min_ppo2 / f_o2
-        });
+    // Gas with its own max/min ppO2, flags default from role.
+    pub fn new(f_o2: f64, f_he: f64, ppo2: f64, min_ppo2: f64, role: GasRole) -> Gas {
+        // At least air is usable at any surface pressure (altitude), only
+        // hypoxic mixes need depth before they can be breathed.
+        let min = Pressure::bar(if f_o2 >= 0.21 { 0.0 } else { min_ppo2 / f_o2 });
         let max = Pressure::bar(ppo2 / f_o2);
         Gas {
             f_o2,
@@ -44,19 +46,63 @@ impl Gas {
             ppo2,
             min_depth: min,
             max_depth: max,
-            use_ascent,
-            use_descent,
-            use_diluent,
+            use_ascent: role != GasRole::Diluent,
+            use_descent: role == GasRole::Bottom || role == GasRole::Travel,
+            use_diluent: role == GasRole::Diluent,
+            role,
         }
     }
     pub fn new_deco(f_o2: f64, f_he: f64) -> Gas {
-        Gas::new(f_o2, f_he, 1.61, 0.21, true, false, false)
+        Gas::new(f_o2, f_he, 1.61, 0.21, GasRole::Deco)
     }
     pub fn new_bottom(f_o2: f64, f_he: f64, ppo2: f64) -> Gas {
-        Gas::new(f_o2, f_he, ppo2, 0.18, true, true, false)
+        Gas::new(f_o2, f_he, ppo2, 0.18, GasRole::Bottom)
     }
     pub fn new_diluent(f_o2: f64, f_he: f64) -> Gas {
-        Gas::new(f_o2, f_he, 1.61, 0.18, false, false, true)
+        Gas::new(f_o2, f_he, 1.61, 0.18, GasRole::Diluent)
+    }
+    pub fn new_travel(f_o2: f64, f_he: f64, ppo2: f64) -> Gas {
+        Gas::new(f_o2, f_he, ppo2, 0.18, GasRole::Travel)
+    }
+    // Gas for role with the role's default ppO2 unless given.
+    pub fn new_role(f_o2: f64, f_he: f64, role: GasRole, ppo2: Option<f64>) -> Gas {
+        match role {
+            GasRole::Bottom => Gas::new_bottom(f_o2, f_he, ppo2.unwrap_or(1.4)),
+            GasRole::Travel => Gas::new_travel(f_o2, f_he, ppo2.unwrap_or(1.4)),
+            GasRole::Deco => Gas::new(f_o2, f_he, ppo2.unwrap_or(1.61), 0.21, role),
+            GasRole::Diluent => Gas::new(f_o2, f_he, ppo2.unwrap_or(1.61), 0.18, role),
+        }
+    }
+
+    // Override the MOD, e.g. switch to 50% at 21m regardless of ppO2.
+    pub fn with_mod(self, depth: Depth, atm: Pressure, water: Water) -> Gas {
+        Gas {
+            max_depth: Pressure::from_depth(depth, atm, water),
+            ..self
+        }
+    }
+    // Override the shallowest depth the gas can be used at.
+    pub fn with_min_depth(self, depth: Depth, atm: Pressure, water: Water) -> Gas {
+        Gas {
+            min_depth: Pressure::from_depth(depth, atm, water),
+            ..self
+        }
+    }
+    pub fn with_use(self, use_ascent: bool, use_descent: bool, use_diluent: bool) -> Gas {
+        Gas {
+            use_ascent,
+            use_descent,
+            use_diluent,
+            ..self
+        }
+    }
+
+    // Absolute pressure range the gas can be used in.
+    pub fn min_pressure(&self) -> Pressure {
+        self.min_depth
+    }
+    pub fn max_pressure(&self) -> Pressure {
+        self.max_depth
     }
 
     pub fn use_gas(&self, depth: Pressure, segment: SegmentType) -> bool {
@@ -71,6 +117,49 @@ impl Gas {
         false
     }
 
+    // Maximum operating depth for the gas's ppO2.
+    pub fn mod_depth(&self, atm: Pressure, water: Water) -> Depth {
+        Depth::from_pressure(self.max_depth, atm, water)
+    }
+
+    // Shallowest depth the gas is breathable at (surface if not hypoxic).
+    pub fn min_depth(&self, atm: Pressure, water: Water) -> Depth {
+        let depth = Depth::from_pressure(self.min_depth, atm, water);
+        if depth < Depth::millimeters(0.0) {
+            Depth::millimeters(0.0)
+        } else {
+            depth
+        }
+    }
+
+    // Equivalent narcotic depth, optionally counting oxygen as narcotic.
+    pub fn end(&self, depth: Depth, atm: Pressure, water: Water, o2_narcotic: bool) -> Depth {
+        let pressure = Pressure::from_depth(depth, atm, water);
+        let narcotic = if o2_narcotic {
+            (self.f_n2 + self.f_o2) * pressure.to_mbar()
+        } else {
+            self.f_n2 * pressure.to_mbar() / 0.79
+        };
+        Depth::from_pressure(Pressure::millibar(narcotic), atm, water)
+    }
+
+    // Equivalent air depth (nitrogen only).
+    pub fn ead(&self, depth: Depth, atm: Pressure, water: Water) -> Depth {
+        let pressure = Pressure::from_depth(depth, atm, water);
+        Depth::from_pressure(
+            Pressure::millibar(self.f_n2 * pressure.to_mbar() / 0.79),
+            atm,
+            water,
+        )
+    }
+
+    // Gas density at depth in g/L.
+    pub fn density(&self, depth: Depth, atm: Pressure, water: Water) -> f64 {
+        let pressure = Pressure::from_depth(depth, atm, water);
+        (self.f_o2 * DENSITY_O2 + self.f_n2 * DENSITY_N2 + self.f_he * DENSITY_HE)
+            * (pressure.to_bar() / 1.01325)
+    }
+
     //int get hashCode => (f_o2 * 1000 + f_he * 1000).ceil();
 }
 
@@ -115,6 +204,111 @@ impl fmt::Display for Gas {
     }
 }
 
+fn parse_percent(s: &str, gas: &str) -> Result<f64, String> {
+    let value = s
+        .trim_end_matches('%')
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid percentage '{}' in gas '{}'.", s, gas))?;
+    if !(0.0..=100.0).contains(&value) {
+        return Err(format!("Percentage '{}' out of range in gas '{}'.", s, gas));
+    }
+    Ok(value / 100.0)
+}
+
+// Parses the mix part of a gas name into (f_o2, f_he).
+fn parse_mix(mix: &str) -> Result<(f64, f64), String> {
+    let lower = mix.to_lowercase();
+    match lower.as_str() {
+        "air" => return Ok((0.21, 0.0)),
+        "o2" | "oxygen" => return Ok((1.0, 0.0)),
+        _ => {}
+    }
+    let idx = lower
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(lower.len());
+    let (prefix, rest) = lower.split_at(idx);
+    let mut parts = rest.split('/');
+    let first = parts.next().unwrap_or("");
+    let second = parts.next();
+    if parts.next().is_some() || first.is_empty() {
+        return Err(format!("Invalid gas '{}'.", mix));
+    }
+    match (prefix, second) {
+        ("" | "ean" | "eanx" | "nx" | "nitrox", None) => Ok((parse_percent(first, mix)?, 0.0)),
+        ("" | "tx" | "trimix", Some(he)) => {
+            Ok((parse_percent(first, mix)?, parse_percent(he, mix)?))
+        }
+        ("hx" | "heliox", None) => {
+            let f_o2 = parse_percent(first, mix)?;
+            Ok((f_o2, 1.0 - f_o2))
+        }
+        ("hx" | "heliox", Some(he)) => Ok((parse_percent(first, mix)?, parse_percent(he, mix)?)),
+        ("ha" | "heliair", None) => {
+            let f_he = parse_percent(first, mix)?;
+            Ok((0.21 * (1.0 - f_he), f_he))
+        }
+        _ => Err(format!("Unknown gas notation '{}'.", mix)),
+    }
+}
+
+impl FromStr for GasRole {
+    type Err = String;
+    fn from_str(s: &str) -> Result<GasRole, String> {
+        match s.to_lowercase().as_str() {
+            "bottom" => Ok(GasRole::Bottom),
+            "deco" => Ok(GasRole::Deco),
+            "dil" | "diluent" => Ok(GasRole::Diluent),
+            "travel" => Ok(GasRole::Travel),
+            _ => Err(format!("Unknown gas role '{}'.", s)),
+        }
+    }
+}
+
+/// Parses "<mix> [role] [ppO2]", for example "18/45", "EAN50 deco",
+/// "Tx21/35 travel 1.4", "Air", "O2 deco @1.6", "Heliox 10/90" or "Heliair 30".
+/// Role defaults to bottom and ppO2 to the role's default.
+impl FromStr for Gas {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Gas, String> {
+        let mut tokens: Vec<&str> = s.split_whitespace().collect();
+        if tokens.is_empty() {
+            return Err("Empty gas.".to_string());
+        }
+        // Allow a space between the notation and the numbers ("EAN 32").
+        let mix = if tokens.len() > 1
+            && tokens[0].chars().all(|c| c.is_ascii_alphabetic())
+            && tokens[1].starts_with(|c: char| c.is_ascii_digit())
+        {
+            let mix = format!("{}{}", tokens[0], tokens[1]);
+            tokens.drain(0..2);
+            mix
+        } else {
+            tokens.remove(0).to_string()
+        };
+        let (f_o2, f_he) = parse_mix(&mix)?;
+        if f_o2 <= 0.0 || f_o2 + f_he > 1.0 + f64::EPSILON {
+            return Err(format!("Gas '{}' is not a valid mix.", s));
+        }
+        let mut role = GasRole::Bottom;
+        let mut ppo2 = None;
+        for token in tokens {
+            let lower = token.to_lowercase();
+            let num = lower.trim_start_matches('@').trim_start_matches("ppo2=");
+            if let Ok(value) = num.parse::<f64>() {
+                if !value.is_finite() || value <= 0.0 {
+                    return Err(format!("Invalid ppO2 '{}' in gas '{}'.", token, s));
+                }
+                ppo2 = Some(value);
+            } else {
+                role = token
+                    .parse()
+                    .map_err(|_| format!("Unknown gas role '{}' in gas '{}'.", token, s))?;
+            }
+        }
+        Ok(Gas::new_role(f_o2, f_he, role, ppo2))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::*;
@@ -130,4 +324,89 @@ mod tests {
             format!("{}", Gas::new_bottom(0.18, 0.45, 1.4))
         );
     }
+
+    #[test]
+    fn end_ead_density() {
+        let atm = Pressure::millibar(1000.0);
+        let water = Water::default();
+        let depth = Depth::meters(30.0);
+        let ean32 = Gas::new_bottom(0.32, 0.0, 1.4);
+        assert!((ean32.end(depth, atm, water, true).to_meters() - 30.0).abs() < 0.01);
+        assert!((ean32.ead(depth, atm, water).to_meters() - 24.43).abs() < 0.01);
+        assert!((ean32.mod_depth(atm, water).to_meters() - 33.75).abs() < 0.01);
+        assert_eq!(0.0, ean32.min_depth(atm, water).to_meters());
+        let tx = Gas::new_bottom(0.18, 0.45, 1.4);
+        assert!((tx.end(depth, atm, water, true).to_meters() - 12.0).abs() < 0.01);
+        assert!((tx.density(depth, atm, water) - 3.16).abs() < 0.01);
+    }
+
+    #[test]
+    fn parse() {
+        let gas: Gas = "18/45".parse().unwrap();
+        assert_eq!(
+            (0.18, 0.45, GasRole::Bottom),
+            (gas.f_o2, gas.f_he, gas.role)
+        );
+        let gas: Gas = "EAN50 deco".parse().unwrap();
+        assert_eq!("50%", format!("{}", gas));
+        assert_eq!(GasRole::Deco, gas.role);
+        let gas: Gas = "Tx21/35 travel @1.3".parse().unwrap();
+        assert_eq!(
+            (0.21, 0.35, 1.3, GasRole::Travel),
+            (gas.f_o2, gas.f_he, gas.ppo2, gas.role)
+        );
+        let gas: Gas = "ean 32 1.6".parse().unwrap();
+        assert_eq!((0.32, 1.6), (gas.f_o2, gas.ppo2));
+        assert_eq!(1.0, "O2 deco".parse::<Gas>().unwrap().f_o2);
+        assert_eq!(0.21, "Air".parse::<Gas>().unwrap().f_o2);
+        assert_eq!(0.9, "Heliox 10".parse::<Gas>().unwrap().f_he);
+        let gas: Gas = "Heliair30".parse().unwrap();
+        assert!((gas.f_o2 - 0.147).abs() < 0.0001 && gas.f_he == 0.3);
+        assert!("60/50".parse::<Gas>().is_err());
+        assert_eq!(
+            Err("Unknown gas role 'sideways' in gas 'EAN32 sideways'.".to_string()),
+            "EAN32 sideways".parse::<Gas>().map(|_| ())
+        );
+        assert!("EAN32 nan".parse::<Gas>().is_err());
+        assert!("EAN32 inf".parse::<Gas>().is_err());
+        assert!("EAN32 @-1.4".parse::<Gas>().is_err());
+        assert!("EANnan".parse::<Gas>().is_err());
+        assert!("Kr21".parse::<Gas>().is_err());
+    }
+
+    #[test]
+    fn custom() {
+        let atm = Pressure::millibar(1013.0);
+        let water = Water::default();
+        let gas =
+            Gas::new(0.5, 0.0, 1.6, 0.16, GasRole::Deco).with_mod(Depth::meters(21.0), atm, water);
+        assert!((gas.mod_depth(atm, water).to_meters() - 21.0).abs() < 0.001);
+        assert!(!gas.use_gas(
+            Pressure::from_depth(Depth::meters(21.5), atm, water),
+            SegmentType::UP
+        ));
+        assert!(gas.use_gas(
+            Pressure::from_depth(Depth::meters(21.0), atm, water),
+            SegmentType::UP
+        ));
+        assert!(!gas.use_gas(atm, SegmentType::DOWN));
+
+        let gas = gas
+            .with_use(true, true, false)
+            .with_min_depth(Depth::meters(6.0), atm, water);
+        assert!(gas.use_gas(
+            Pressure::from_depth(Depth::meters(10.0), atm, water),
+            SegmentType::DOWN
+        ));
+        assert!(!gas.use_gas(atm, SegmentType::DOWN));
+        assert_eq!(6.0, gas.min_depth(atm, water).to_meters().round());
+    }
+
+    #[test]
+    fn hypoxic_at_altitude() {
+        let dive = Dive::default().with_altitude(2500.0);
+        let gas = Gas::new_bottom(0.18, 0.45, 1.4);
+        assert!(!gas.use_gas(dive.atm_pressure, SegmentType::DOWN));
+        assert!(gas.use_gas(Pressure::millibar(1013.0), SegmentType::DOWN));
+    }
 }
@@ -0,0 +1,190 @@
+use crate::dive::*;
+use crate::gas::Gas;
+use crate::segment::*;
+use crate::types::*;
+
+// How long (minutes) after a switch to look for a transient rise in tissue tension.
+const ICD_WINDOW: f64 = 10.0;
+const ICD_STEP: f64 = 0.5;
+
+pub struct IcdTissue {
+    pub compartment: usize,
+    pub increase: f64, // bar, peak rise over staying on the previous gas
+    pub time: f64,     // minutes after the switch the peak occurs
+}
+
+pub struct IcdSwitch {
+    pub segment: usize, // first segment on the new gas
+    pub depth: Pressure,
+    pub from: Gas,
+    pub to: Gas,
+    pub n2_increase: f64, // fraction
+    pub he_decrease: f64, // fraction
+    pub tissue: Option<IcdTissue>,
+    pub warnings: Vec<String>,
+}
+
+// Simulates the new gas at the switch depth next to staying on the previous
+// gas and finds the largest extra rise in total inert gas tension of any
+// compartment caused by the switch.
+fn tissue_check(
+    dive: &Dive,
+    constants: &TissueConstants,
+    comps_in: &Compartments,
+    depth: Pressure,
+    from: Gas,
+    to: Gas,
+) -> Option<IcdTissue> {
+    let mut worst: Option<IcdTissue> = None;
+    let mut switched = Compartments::new_copy(comps_in);
+    let mut stayed = Compartments::new_copy(comps_in);
+    let mut time = 0.0;
+    while time < ICD_WINDOW {
+        switched = calc_bottom(&switched, constants, dive.alveolar, depth, ICD_STEP, to);
+        stayed = calc_bottom(&stayed, constants, dive.alveolar, depth, ICD_STEP, from);
+        time += ICD_STEP;
+        for i in 0..constants.compartments {
+            let increase = (switched.nitrogen[i] + switched.helium[i])
+                - (stayed.nitrogen[i] + stayed.helium[i]);
+            if increase > 0.0 && worst.as_ref().is_none_or(|w| increase > w.increase) {
+                worst = Some(IcdTissue {
+                    compartment: i,
+                    increase,
+                    time,
+                });
+            }
+        }
+    }
+    worst
+}
+
+/// Checks every gas switch in segments for isobaric counter-diffusion: the rule
+/// of fifths (N2 increase no more than a fifth of the He decrease) and, given
+/// constants, a transient rise in tissue inert gas tension after the switch.
+pub fn check_icd(
+    dive: &Dive,
+    segments: &[Segment],
+    constants: Option<&TissueConstants>,
+) -> Result<Vec<IcdSwitch>, String> {
+    if let Some(c) = constants {
+        for seg in segments {
+            check_compartments(&seg.compartments, c)?;
+        }
+    }
+    let mut switches = Vec::new();
+    for i in 1..segments.len() {
+        let prev = &segments[i - 1];
+        let seg = &segments[i];
+        if prev.gas == seg.gas {
+            continue;
+        }
+        let n2_increase = seg.gas.f_n2 - prev.gas.f_n2;
+        let he_decrease = prev.gas.f_he - seg.gas.f_he;
+        let depth_str = dive.format_depth(prev.depth.to_depth(dive.atm_pressure, dive.water));
+        let mut warnings = Vec::new();
+        if n2_increase > 0.0 && n2_increase > he_decrease / 5.0 {
+            warnings.push(format!(
+                "Switch {} to {} at {}: N2 up {:.0}% exceeds a fifth of He down {:.0}%.",
+                prev.gas,
+                seg.gas,
+                depth_str,
+                n2_increase * 100.0,
+                he_decrease * 100.0
+            ));
+        }
+        let tissue = constants
+            .and_then(|c| tissue_check(dive, c, &prev.compartments, prev.depth, prev.gas, seg.gas));
+        if let Some(t) = &tissue {
+            warnings.push(format!(
+                "Switch {} to {} at {}: compartment {} tension {:.3}bar above staying after {:.1}min.",
+                prev.gas,
+                seg.gas,
+                depth_str,
+                t.compartment + 1,
+                t.increase,
+                t.time
+            ));
+        }
+        switches.push(IcdSwitch {
+            segment: i,
+            depth: prev.depth,
+            from: prev.gas,
+            to: seg.gas,
+            n2_increase,
+            he_decrease,
+            tissue,
+            warnings,
+        });
+    }
+    Ok(switches)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn fifths() {
+        let dive: Dive = Default::default();
+        let comps = Compartments::new_surface(1013.0, Alveolar::default(), COMPARTMENTS);
+        let segments = vec![SegmentIn::new_bottom(Depth::meters(60.0), 25.0, 1.4)];
+        let gasses = vec![Gas::new_bottom(0.18, 0.45, 1.4), Gas::new_deco(0.32, 0.0)];
+        let segs = calc_deco_c(&dive, &comps, &segments, &gasses)
+            .unwrap()
+            .segments;
+        let switches = check_icd(&dive, &segs, Some(&CONSTANTS_C)).unwrap();
+        assert_eq!(1, switches.len());
+        assert!(switches[0].n2_increase > switches[0].he_decrease / 5.0);
+        // He leaves faster than N2 comes in so no tissue rises over staying.
+        assert!(switches[0].tissue.is_none());
+        assert_eq!(1, switches[0].warnings.len());
+
+        let gasses = vec![Gas::new_bottom(0.18, 0.45, 1.4), Gas::new_deco(1.0, 0.0)];
+        let segs = calc_deco_c(&dive, &comps, &segments, &gasses)
+            .unwrap()
+            .segments;
+        let switches = check_icd(&dive, &segs, None).unwrap();
+        assert_eq!(1, switches.len());
+        assert!(switches[0].warnings.is_empty());
+        let switches = check_icd(&dive, &segs, Some(&CONSTANTS_C)).unwrap();
+        assert!(switches[0].tissue.is_none());
+        assert!(switches[0].warnings.is_empty());
+
+        // Slow tissues still on gassing on either gas are not counter-diffusion.
+        let gasses = vec![Gas::new_bottom(0.18, 0.45, 1.4), Gas::new_deco(0.5, 0.0)];
+        let segs = calc_deco_c(&dive, &comps, &segments, &gasses)
+            .unwrap()
+            .segments;
+        let switches = check_icd(&dive, &segs, Some(&CONSTANTS_C)).unwrap();
+        assert!(switches[0]
+            .tissue
+            .as_ref()
+            .is_none_or(|t| t.compartment < 15));
+
+        // Helium diffusing in faster than N2 leaves on the descent switch from
+        // a travel gas to trimix raises the fast tissues over staying on air.
+        let segments = vec![SegmentIn::new_bottom(Depth::meters(80.0), 20.0, 1.4)];
+        let gasses = vec![
+            Gas::new_travel(0.21, 0.0, 1.4),
+            Gas::new_bottom(0.14, 0.86, 1.4),
+        ];
+        let segs = calc_deco_c(&dive, &comps, &segments, &gasses)
+            .unwrap()
+            .segments;
+        let switches = check_icd(&dive, &segs, Some(&CONSTANTS_C)).unwrap();
+        assert_eq!("14/86", format!("{}", switches[0].to));
+        let tissue = switches[0].tissue.as_ref().unwrap();
+        assert_eq!(1, tissue.compartment);
+        assert!((tissue.increase - 0.402).abs() < 0.0005);
+        assert_eq!(7.5, tissue.time);
+        assert_eq!(
+            vec!["Switch 21% to 14/86 at 3m: compartment 2 tension 0.402bar above staying after 7.5min."],
+            switches[0].warnings
+        );
+
+        let mut short = segs;
+        short[0].compartments = Compartments::new_empty(12);
+        assert!(check_icd(&dive, &short, None).is_ok());
+        assert!(check_icd(&dive, &short, Some(&CONSTANTS_C)).is_err());
+    }
+}
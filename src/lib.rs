@@ -7,6 +7,9 @@ pub use dive_consts::*;
 pub mod segment_type;
 pub use segment_type::*;
 
+pub mod alveolar;
+pub use alveolar::*;
+
 pub mod segment;
 pub use segment::*;
 
@@ -19,6 +22,30 @@ pub use otu_cns::*;
 pub mod dive;
 pub use dive::*;
 
+pub mod dive_plan;
+pub use dive_plan::*;
+
+pub mod profile;
+pub use profile::*;
+
+pub mod ratio_deco;
+pub use ratio_deco::*;
+
+pub mod bottom_time;
+pub use bottom_time::*;
+
+pub mod contingency;
+pub use contingency::*;
+
+pub mod best_mix;
+pub use best_mix::*;
+
+pub mod exposure;
+pub use exposure::*;
+
+pub mod icd;
+pub use icd::*;
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -13,30 +13,40 @@ fn main() {
     let dive = Dive {
         ..Default::default()
     };
-    let segs = calc_deco_c(
+    let plan = calc_deco_c(
         &dive,
-        &Compartments::new_surface(1013.0, PARTIAL_WATER, COMPARTMENTS),
+        &Compartments::new_surface(1013.0, Alveolar::default(), COMPARTMENTS),
         &segments,
         &gasses,
     )
     .unwrap();
 
-    let mut tot = 0;
-    let mut cns = 0.0;
-    let mut otu = 0.0;
-    for s in segs {
+    for (s, run_time) in plan.segments.iter().zip(plan.run_times.iter()) {
         println!(
-            "{}   {}({})   {} otu {} cns {}",
-            s.depth.to_depth(dive.atm_pressure).to_meters(),
+            "{}   {}({})   {}   {} otu {} cns {}",
+            dive.format_depth(s.depth.to_depth(dive.atm_pressure, dive.water)),
             s.time,
             s.raw_time,
+            run_time,
             s.gas,
             s.otu_cns.otu,
             s.otu_cns.cns
         );
-        tot += s.time;
-        cns += s.otu_cns.cns;
-        otu += s.otu_cns.otu;
+        for w in &s.warnings {
+            println!("    WARNING: {}", w);
+        }
+    }
+    println!(
+        "Run time: {}, TTS: {}, deco: {}, max depth: {}, average depth: {}, cns: {}, otu: {}",
+        plan.run_time,
+        plan.tts,
+        plan.deco_time,
+        dive.format_depth(plan.max_depth),
+        dive.format_depth(plan.average_depth),
+        plan.otu_cns.cns,
+        plan.otu_cns.otu
+    );
+    for (gas, volume) in &plan.gas_usage {
+        println!("{}: {}", gas, dive.format_volume(*volume));
     }
-    println!("Run time: {}, cns: {}, otu: {}", tot, cns, otu);
 }
@@ -1,14 +1,110 @@
+use crate::dive::Dive;
 use crate::gas::Gas;
+use crate::segment::Segment;
 use crate::types::*;
 
 use std::ops::Add;
 use std::ops::AddAssign;
 
+// NOAA single exposure limits as linear segments of limit (minutes) against ppO2.
 const _CNS_PPO2SEGMENTS: usize = 7;
 const _CNS_PPO2LO: [f64; 7] = [0.5, 0.6, 0.7, 0.8, 0.9, 1.1, 1.5];
-const _CNS_PPO2HI: [f64; 7] = [0.6, 0.7, 0.8, 0.9, 1.1, 1.5, 100.0];
+const _CNS_PPO2HI: [f64; 7] = [0.6, 0.7, 0.8, 0.9, 1.1, 1.5, 1.6];
 const _CNS_LIMIT_SLOPE: [f64; 7] = [-1800.0, -1500.0, -1200.0, -900.0, -600.0, -300.0, -750.0];
 const _CNS_LIMIT_INTERCEPT: [f64; 7] = [1800.0, 1620.0, 1410.0, 1170.0, 900.0, 570.0, 1245.0];
+// Limits at the ends of the last NOAA segment, extrapolated log-linearly above 1.6.
+const _CNS_LIMIT_1_5: f64 = 120.0;
+const _CNS_LIMIT_1_6: f64 = 45.0;
+// Exponential fit to the NOAA table: limit = 1 / (60 * exp(A + B * ppO2)).
+const _CNS_EXP_A: f64 = -11.7853;
+const _CNS_EXP_B: f64 = 1.93873;
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum CnsModel {
+    // NOAA table, extrapolated above ppO2 1.6.
+    #[default]
+    Noaa,
+    // NOAA table, ppO2 above 1.6 is an error.
+    NoaaStrict,
+    // Exponential fit to the NOAA table.
+    Exponential,
+}
+
+/// CNS exposure limit in minutes at po2, None below 0.5 (no CNS loading).
+pub fn cns_limit(po2: f64, model: CnsModel) -> Result<Option<f64>, String> {
+    if po2 <= _CNS_PPO2LO[0] {
+        return Ok(None);
+    }
+    let max = _CNS_PPO2HI[_CNS_PPO2SEGMENTS - 1];
+    match model {
+        CnsModel::Exponential => Ok(Some(1.0 / (60.0 * (_CNS_EXP_A + _CNS_EXP_B * po2).exp()))),
+        CnsModel::NoaaStrict if po2 > max + 1e-9 => Err(format!(
+            "ppO2 {:.2} is above the NOAA CNS table limit of {:.1}.",
+            po2, max
+        )),
+        _ if po2 > max => Ok(Some(
+            _CNS_LIMIT_1_6 * (extrapolation_k() * (po2 - max)).exp(),
+        )),
+        _ => {
+            let mut x = 0;
+            while po2 > _CNS_PPO2HI[x] {
+                x += 1;
+            }
+            Ok(Some(_CNS_LIMIT_SLOPE[x] * po2 + _CNS_LIMIT_INTERCEPT[x]))
+        }
+    }
+}
+
+// Exponent of the log-linear limit above the NOAA table (negative).
+fn extrapolation_k() -> f64 {
+    (_CNS_LIMIT_1_6 / _CNS_LIMIT_1_5).ln()
+        / (_CNS_PPO2HI[_CNS_PPO2SEGMENTS - 1] - _CNS_PPO2LO[_CNS_PPO2SEGMENTS - 1])
+}
+
+// Integral of 1 / limit over ppO2 from lo to hi (lo >= 0.5), exact for each
+// part: Baker's log formula on the NOAA segments and the exponential limits
+// above 1.6 and for the exponential model.
+fn cns_integral(lo: f64, hi: f64, model: CnsModel) -> Result<f64, String> {
+    if hi <= lo {
+        return Ok(0.0);
+    }
+    if let CnsModel::Exponential = model {
+        let rate = |p: f64| 60.0 * (_CNS_EXP_A + _CNS_EXP_B * p).exp();
+        return Ok((rate(hi) - rate(lo)) / _CNS_EXP_B);
+    }
+    // Check the top of the range against the model.
+    cns_limit(hi, model)?;
+    let mut cns = 0.0;
+    for x in 0.._CNS_PPO2SEGMENTS {
+        let a = lo.max(_CNS_PPO2LO[x]);
+        let b = hi.min(_CNS_PPO2HI[x]);
+        if b > a {
+            let m = _CNS_LIMIT_SLOPE[x];
+            let c = _CNS_LIMIT_INTERCEPT[x];
+            cns += ((m * b + c) / (m * a + c)).ln() / m;
+        }
+    }
+    let max = _CNS_PPO2HI[_CNS_PPO2SEGMENTS - 1];
+    if hi > max {
+        let k = extrapolation_k();
+        let a = lo.max(max);
+        cns += ((-k * (hi - max)).exp() - (-k * (a - max)).exp()) / (-k * _CNS_LIMIT_1_6);
+    }
+    Ok(cns)
+}
+
+// CNS% half-time on the surface in minutes.
+pub const CNS_HALF_TIME: f64 = 90.0;
+
+// REPEX OTU limits by length of the exposure series in days (index 0 is one day).
+pub const REPEX_DAILY: [f64; 14] = [
+    850.0, 700.0, 620.0, 525.0, 460.0, 420.0, 380.0, 350.0, 330.0, 310.0, 300.0, 300.0, 300.0,
+    300.0,
+];
+pub const REPEX_TOTAL: [f64; 14] = [
+    850.0, 1400.0, 1860.0, 2100.0, 2300.0, 2520.0, 2660.0, 2800.0, 2970.0, 3100.0, 3300.0, 3600.0,
+    3900.0, 4200.0,
+];
 
 #[derive(Copy, Clone)]
 pub struct OtuCns {
@@ -40,39 +136,35 @@ impl AddAssign for OtuCns {
 /// Oxygen Toxicity Calculations by Erik C. Baker, P.E.
 /// Link as of writing at: https://www.shearwater.com/wp-content/uploads/2012/08/Oxygen_Toxicity_Calculations.pdf
 /// Calculates otu and cns for a bottom segment.
-pub fn bottom(depth: Pressure, time: f64, gas: Gas) -> OtuCns {
+pub fn bottom(depth: Pressure, time: f64, gas: Gas, model: CnsModel) -> Result<OtuCns, String> {
     let po2 = gas.f_o2 * (depth.to_mbar() / 1000.0);
     let otu = if po2 <= 0.5 {
         0.0
     } else {
         time * ((0.5 / (po2 - 0.5)).powf(-5.0 / 6.0))
     };
-    let mut cns = 0.0;
-    let mut tlim = 0.0;
-    if po2 > _CNS_PPO2LO[0] {
-        for x in 0.._CNS_PPO2SEGMENTS {
-            if po2 > _CNS_PPO2LO[x] && po2 <= _CNS_PPO2HI[x] {
-                tlim = _CNS_LIMIT_SLOPE[x] * po2 + _CNS_LIMIT_INTERCEPT[x];
-            }
-        }
-        cns = if tlim > 0.0 { time / tlim } else { 0.0 };
-    }
-    OtuCns {
+    let cns = match cns_limit(po2, model)? {
+        Some(tlim) => time / tlim,
+        None => 0.0,
+    };
+    Ok(OtuCns {
         otu,
         cns: cns * 100.0,
-    }
+    })
 }
 
 /// Algorithm initially from paper:
 /// Oxygen Toxicity Calculations by Erik C. Baker, P.E.
 /// Link as of writing at: https://www.shearwater.com/wp-content/uploads/2012/08/Oxygen_Toxicity_Calculations.pdf
-/// Calculates otu and cns for an ascent/descent segment.
+/// Calculates otu and cns for an ascent/descent segment, CNS is integrated
+/// exactly over the ppO2 range with the same limits as bottom().
 pub fn descent(
     rate_mbar: DepthChange,
     from_depth: Pressure,
     to_depth: Pressure,
     gas: Gas,
-) -> OtuCns {
+    model: CnsModel,
+) -> Result<OtuCns, String> {
     let time = (to_depth.to_mbar() - from_depth.to_mbar()) / rate_mbar.to_mbar();
     let maxata = to_depth.to_mbar().max(from_depth.to_mbar()) / 1000.0;
     let minata = to_depth.to_mbar().min(from_depth.to_mbar()) / 1000.0;
@@ -86,50 +178,208 @@ pub fn descent(
         otu = 3.0 / 11.0 * time / (maxpo2 - lowpo2)
             * (((maxpo2 - 0.5f64) / 0.5f64).powf(11.0 / 6.0))
             - (((lowpo2 - 0.5f64) / 0.5f64).powf(11.0 / 6.0));
-        let mut otime = Vec::with_capacity(_CNS_PPO2SEGMENTS);
-        otime.resize(_CNS_PPO2SEGMENTS, 0.0);
-        let mut po2o = Vec::with_capacity(_CNS_PPO2SEGMENTS);
-        po2o.resize(_CNS_PPO2SEGMENTS, 0.0);
-        let mut po2f = Vec::with_capacity(_CNS_PPO2SEGMENTS);
-        po2f.resize(_CNS_PPO2SEGMENTS, 0.0);
-        let mut segpo2 = Vec::with_capacity(_CNS_PPO2SEGMENTS);
-        segpo2.resize(_CNS_PPO2SEGMENTS, 0.0);
-        let up = from_depth > to_depth;
-        for i in 0.._CNS_PPO2SEGMENTS {
-            if maxpo2 > _CNS_PPO2LO[i] && lowpo2 <= _CNS_PPO2HI[i] {
-                if (maxpo2 >= _CNS_PPO2HI[i]) && (lowpo2 < _CNS_PPO2LO[i]) {
-                    po2o[i] = if up { _CNS_PPO2HI[i] } else { _CNS_PPO2LO[i] };
-                    po2f[i] = if !up { _CNS_PPO2HI[i] } else { _CNS_PPO2LO[i] };
-                } else if (maxpo2 < _CNS_PPO2HI[i]) && (lowpo2 <= _CNS_PPO2LO[i]) {
-                    po2o[i] = if up { maxpo2 } else { _CNS_PPO2LO[i] };
-                    po2f[i] = if !up { maxpo2 } else { _CNS_PPO2LO[i] };
-                } else if (lowpo2 > _CNS_PPO2LO[i]) && (maxpo2 >= _CNS_PPO2HI[i]) {
-                    po2o[i] = if up { _CNS_PPO2HI[i] } else { lowpo2 };
-                    po2f[i] = if !up { _CNS_PPO2HI[i] } else { lowpo2 };
-                } else {
-                    po2o[i] = if up { maxpo2 } else { lowpo2 };
-                    po2f[i] = if !up { maxpo2 } else { lowpo2 };
-                }
-                segpo2[i] = po2f[i] - po2o[i];
-                otime[i] = time * (segpo2[i] / (maxpo2 - lowpo2)).abs();
-            } else {
-                otime[i] = 0.0;
-                po2o[i] = 0.0;
-                po2f[i] = 0.0;
-                segpo2[i] = 0.0;
-            }
+        // ppO2 changes linearly with time.
+        cns = time / (maxpo2 - lowpo2) * cns_integral(lowpo2, maxpo2, model)?;
+    }
+
+    Ok(OtuCns {
+        otu,
+        cns: cns * 100.0,
+    })
+}
+
+/// CNS% remaining after minutes on the surface.
+pub fn cns_surface_decay(cns: f64, minutes: f64) -> f64 {
+    cns * 0.5f64.powf(minutes / CNS_HALF_TIME)
+}
+
+/// Oxygen exposure over a series of dives, CNS% decaying between dives and
+/// OTUs accumulating per day.
+#[derive(Clone)]
+pub struct OxygenExposure {
+    pub cns: f64,
+    pub otu_days: Vec<f64>,
+}
+
+impl Default for OxygenExposure {
+    fn default() -> OxygenExposure {
+        OxygenExposure::new()
+    }
+}
+
+impl OxygenExposure {
+    pub fn new() -> OxygenExposure {
+        OxygenExposure {
+            cns: 0.0,
+            otu_days: vec![0.0],
+        }
+    }
+
+    pub fn otu_today(&self) -> f64 {
+        *self.otu_days.last().unwrap_or(&0.0)
+    }
+
+    pub fn otu_total(&self) -> f64 {
+        self.otu_days.iter().sum()
+    }
+
+    pub fn surface_interval(&mut self, minutes: f64) {
+        self.cns = cns_surface_decay(self.cns, minutes);
+    }
+
+    // Starts the next day of the series, surface_interval() still applies to CNS.
+    pub fn next_day(&mut self) {
+        self.otu_days.push(0.0);
+    }
+
+    pub fn add(&mut self, otu_cns: OtuCns) {
+        self.cns += otu_cns.cns;
+        if self.otu_days.is_empty() {
+            self.otu_days.push(0.0);
         }
-        for i in 0.._CNS_PPO2SEGMENTS {
-            if otime[i] > 0.0 {
-                let tlimi = _CNS_LIMIT_SLOPE[i] * po2o[i] + _CNS_LIMIT_INTERCEPT[i];
-                let mk = _CNS_LIMIT_SLOPE[i] * (segpo2[i] / otime[i]);
-                cns += 1.0 / mk * (((tlimi + mk * otime[i]).abs()).ln() - (tlimi.abs()).ln());
+        let last = self.otu_days.len() - 1;
+        self.otu_days[last] += otu_cns.otu;
+    }
+
+    /// Adds a dive to the series and returns warnings for exceeding the dive's
+    /// CNS/OTU limits or the REPEX daily/total limits.
+    pub fn add_dive(&mut self, dive: &Dive, segments: &[Segment]) -> Vec<String> {
+        for s in segments {
+            self.add(s.otu_cns);
+        }
+        self.warnings(dive)
+    }
+
+    pub fn warnings(&self, dive: &Dive) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.cns > dive.max_cns {
+            warnings.push(format!(
+                "CNS {:.0}% exceeds {:.0}%.",
+                self.cns, dive.max_cns
+            ));
+        }
+        if self.otu_today() > dive.max_otu {
+            warnings.push(format!(
+                "Daily OTU {:.0} exceeds {:.0}.",
+                self.otu_today(),
+                dive.max_otu
+            ));
+        }
+        let days = self.otu_days.len().max(1);
+        let idx = days.min(REPEX_DAILY.len()) - 1;
+        let extra = days.saturating_sub(REPEX_TOTAL.len()) as f64;
+        let daily = REPEX_DAILY[idx];
+        let total = REPEX_TOTAL[idx] + extra * REPEX_DAILY[REPEX_DAILY.len() - 1];
+        for (day, otu) in self.otu_days.iter().enumerate() {
+            if *otu > daily {
+                warnings.push(format!(
+                    "Day {} OTU {:.0} exceeds REPEX daily limit {:.0} for {} days.",
+                    day + 1,
+                    otu,
+                    daily,
+                    days
+                ));
             }
         }
+        if self.otu_total() > total {
+            warnings.push(format!(
+                "Total OTU {:.0} exceeds REPEX limit {:.0} for {} days.",
+                self.otu_total(),
+                total,
+                days
+            ));
+        }
+        warnings
     }
+}
 
-    OtuCns {
-        otu,
-        cns: cns * 100.0,
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn repetitive() {
+        assert!((cns_surface_decay(80.0, 90.0) - 40.0).abs() < 0.0001);
+
+        let dive: Dive = Default::default();
+        let mut exposure = OxygenExposure::new();
+        exposure.add(OtuCns {
+            otu: 400.0,
+            cns: 70.0,
+        });
+        assert!(exposure.warnings(&dive).len() == 1);
+        exposure.surface_interval(180.0);
+        assert!((exposure.cns - 17.5).abs() < 0.0001);
+        exposure.next_day();
+        exposure.add(OtuCns {
+            otu: 250.0,
+            cns: 30.0,
+        });
+        assert!(exposure.warnings(&dive).is_empty());
+        exposure.next_day();
+        exposure.add(OtuCns {
+            otu: 650.0,
+            cns: 30.0,
+        });
+        // Over max_otu and the three day REPEX daily limit of 620.
+        let warnings = exposure.warnings(&dive);
+        assert_eq!(2, warnings.len());
+    }
+
+    #[test]
+    fn cns_models() {
+        let gas = Gas::new_deco(1.0, 0.0);
+        let depth = Pressure::bar(1.8);
+        let cns = bottom(depth, 10.0, gas, CnsModel::Noaa).unwrap().cns;
+        assert!(cns > 100.0 * 10.0 / 45.0);
+        assert!(bottom(depth, 10.0, gas, CnsModel::NoaaStrict).is_err());
+        let cns = bottom(Pressure::bar(1.4), 150.0, gas, CnsModel::Exponential)
+            .unwrap()
+            .cns;
+        assert!((cns - 100.0).abs() < 5.0);
+        // Segment boundaries agree from both sides.
+        let lo = cns_limit(1.1, CnsModel::Noaa).unwrap().unwrap();
+        let hi = cns_limit(1.1 + 1e-9, CnsModel::Noaa).unwrap().unwrap();
+        assert!((lo - 240.0).abs() < 0.001 && (hi - 240.0).abs() < 0.001);
+        assert_eq!(None, cns_limit(0.5, CnsModel::Noaa).unwrap());
+
+        // A slow change over a small range matches a level segment.
+        let rate = DepthChange::descent_pressure(Pressure::millibar(1.0));
+        let change = descent(
+            rate,
+            Pressure::bar(1.3),
+            Pressure::bar(1.31),
+            gas,
+            CnsModel::Noaa,
+        );
+        let level = bottom(Pressure::bar(1.305), 10.0, gas, CnsModel::Noaa).unwrap();
+        assert!((change.unwrap().cns - level.cns).abs() < 0.001);
+        let rate = DepthChange::ascent_pressure(Pressure::millibar(1000.0));
+        let ascent = descent(
+            rate,
+            Pressure::bar(1.7),
+            Pressure::bar(1.0),
+            gas,
+            CnsModel::NoaaStrict,
+        );
+        assert!(ascent.is_err());
+
+        // Exact integrals match a fine sum of level segments on every model.
+        let rate = DepthChange::ascent_pressure(Pressure::millibar(100.0));
+        for model in [CnsModel::Noaa, CnsModel::Exponential] {
+            let change = descent(rate, Pressure::bar(1.8), Pressure::bar(0.4), gas, model)
+                .unwrap()
+                .cns;
+            let steps = 10000;
+            let sum: f64 = (0..steps)
+                .map(|i| {
+                    let bar = 0.4 + 1.4 * (i as f64 + 0.5) / steps as f64;
+                    bottom(Pressure::bar(bar), 14.0 / steps as f64, gas, model)
+                        .unwrap()
+                        .cns
+                })
+                .sum();
+            assert!((change - sum).abs() < 1e-4 * sum);
+        }
     }
 }
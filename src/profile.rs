@@ -0,0 +1,171 @@
+use crate::dive::*;
+use crate::dive_plan::DivePlan;
+use crate::gas::Gas;
+use crate::segment::*;
+use crate::segment_type::SegmentType;
+use crate::types::*;
+
+// Runs profile from depth through the tissue model without adding any stops.
+// A LEVEL entry moves to its depth at the dive's rates and stays there until
+// time has passed since it started, as in calc_deco; an UP or DOWN entry takes
+// time to get to its depth (the dive's rate if 0).
+pub(crate) fn run_profile(
+    dive: &Dive,
+    constants: &TissueConstants,
+    gasses: &[Gas],
+    comps_in: &Compartments,
+    depth: Pressure,
+    profile: &[SegmentIn],
+) -> Result<(Vec<Segment>, Compartments), String> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut comps_out = Compartments::new_copy(comps_in);
+    let mut last_depth = depth;
+    let mut ascending = false;
+    for s in profile {
+        let depth = Pressure::from_depth(s.depth, dive.atm_pressure, dive.water);
+        let mut raw_time = 0.0;
+        if depth != last_depth {
+            let change = depth - last_depth;
+            let rate = if s.segment_type != SegmentType::LEVEL && s.time > 0.0 {
+                DepthChange::descent_pressure(Pressure::millibar(change.to_mbar() / s.time))
+            } else if depth > last_depth {
+                dive.descent_rate
+            } else {
+                dive.ascent_rate
+            };
+            let (comps, seg) = change_depth(
+                dive, constants, gasses, rate, last_depth, depth, s.setpoint, &comps_out,
+            )?;
+            ascending = depth < last_depth;
+            raw_time = seg.raw_time;
+            segments.push(seg);
+            comps_out = comps;
+            last_depth = depth;
+        }
+        if s.segment_type == SegmentType::LEVEL && s.time > raw_time {
+            // Stops on the way up breathe the deco gas.
+            let gas_type = if ascending {
+                SegmentType::UP
+            } else {
+                SegmentType::LEVEL
+            };
+            let gas = find_gas(dive, gasses, depth, gas_type, s.setpoint)?;
+            let (comps, seg) = bottom_gas(
+                dive,
+                constants,
+                gas,
+                depth,
+                s.time - raw_time,
+                s.setpoint,
+                &comps_out,
+            )?;
+            segments.push(Segment {
+                time: (s.time - raw_time.ceil()).max(0.0) as u32,
+                ..seg
+            });
+            comps_out = comps;
+        }
+    }
+    Ok((segments, comps_out))
+}
+
+/// A segment that ends above the ceiling at the evaluation GF.
+pub struct CeilingViolation {
+    pub segment: usize,
+    pub depth: Pressure,
+    pub ceiling: Pressure,
+}
+
+pub struct ProfileEvaluation {
+    pub plan: DivePlan,
+    pub violations: Vec<CeilingViolation>,
+    pub gf99: f64, // at the end of the profile
+}
+
+/// Runs a fully written profile (levels, ascents and stops, from the surface)
+/// through the tissue model without adding stops and checks the ceiling at gf
+/// at the end of every segment. LEVEL times include the move to the level, as
+/// in calc_deco.
+pub fn evaluate_profile(
+    dive: &Dive,
+    compartments: &Compartments,
+    constants: &TissueConstants,
+    profile: &[SegmentIn],
+    gasses: &[Gas],
+    gf: f64,
+) -> Result<ProfileEvaluation, String> {
+    if profile.is_empty() {
+        return Err("Must provide segment(s) to evaluate.".to_string());
+    }
+    check_compartments(compartments, constants)?;
+    let (mut segments, comps_out) = run_profile(
+        dive,
+        constants,
+        gasses,
+        compartments,
+        dive.atm_pressure,
+        profile,
+    )?;
+    let mut violations = Vec::new();
+    for (i, seg) in segments.iter_mut().enumerate() {
+        tissue_state(dive, constants, seg, gf);
+        if seg.ceiling > seg.depth {
+            violations.push(CeilingViolation {
+                segment: i,
+                depth: seg.depth,
+                ceiling: seg.ceiling,
+            });
+        }
+    }
+    oxygen_warnings(dive, &mut segments);
+    let gf99 = segments.last().map_or(0.0, |s| s.gf99);
+    // The ascent starts after the last segment at the deepest depth.
+    let max_depth = segments
+        .iter()
+        .map(|s| s.depth)
+        .fold(dive.atm_pressure, |a, b| a.max(b));
+    let bottom_segments = segments
+        .iter()
+        .rposition(|s| s.depth == max_depth)
+        .map_or(0, |i| i + 1);
+    Ok(ProfileEvaluation {
+        plan: DivePlan::new(dive, segments, bottom_segments, comps_out),
+        violations,
+        gf99,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn evaluate() {
+        let dive: Dive = Default::default();
+        let comps = Compartments::new_surface(1013.0, Alveolar::default(), COMPARTMENTS);
+        let gasses = vec![Gas::new_bottom(0.21, 0.0, 1.4), Gas::new_deco(0.5, 0.0)];
+        let level = |m, t| SegmentIn::new_bottom(Depth::meters(m), t, 1.4);
+        let mut profile = vec![level(40.0, 25.0), level(0.0, 0.0)];
+        let eval = evaluate_profile(&dive, &comps, &CONSTANTS_C, &profile, &gasses, 0.8).unwrap();
+        assert!(!eval.violations.is_empty());
+        assert!(eval.gf99 > 80.0);
+
+        profile.pop();
+        for (m, t) in [(12.0, 2.0), (9.0, 3.0), (6.0, 6.0), (3.0, 12.0), (0.0, 0.0)] {
+            profile.push(level(m, t));
+        }
+        let eval = evaluate_profile(&dive, &comps, &CONSTANTS_C, &profile, &gasses, 0.8).unwrap();
+        assert!(eval.violations.is_empty());
+        assert!(eval.gf99 <= 80.0);
+        assert!(eval.plan.otu_cns.otu > 0.0);
+
+        // A LEVEL time includes the descent, as in calc_deco.
+        let bottom = [level(40.0, 25.0)];
+        let eval = evaluate_profile(&dive, &comps, &CONSTANTS_C, &bottom, &gasses, 0.8).unwrap();
+        let (_, planned, _) =
+            initial_segments(&dive, &comps, &CONSTANTS_C, &bottom, &gasses).unwrap();
+        let raw_time: f64 = eval.plan.segments.iter().map(|s| s.raw_time).sum();
+        assert!((raw_time - 25.0).abs() < 1e-9);
+        assert_eq!(planned.nitrogen, eval.plan.compartments.nitrogen);
+    }
+}
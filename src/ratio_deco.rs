@@ -0,0 +1,197 @@
+use crate::dive::*;
+use crate::dive_plan::DivePlan;
+use crate::gas::{Gas, GasRole};
+use crate::profile::run_profile;
+use crate::segment::*;
+use crate::segment_type::SegmentType;
+use crate::types::*;
+
+// Deep stops are a minute each until the first switch.
+const RATIO_SWITCH: f64 = 21.0; // m
+const RATIO_LAST_SPREAD: f64 = 9.0; // m
+const RATIO_O2_STOP: f64 = 6.0; // m
+
+/// Ratio Deco stops for bottom_time at max_depth, deepest first: a minute
+/// per stop from half the max depth to 21m, then the deco time (bottom time
+/// times ratio) less those minutes, half at 6m and the rest spread over 21m to 9m.
+pub fn ratio_deco_stops(
+    dive: &Dive,
+    max_depth: Depth,
+    bottom_time: f64,
+    ratio: f64,
+) -> Vec<(Depth, f64)> {
+    let step = dive.stop_size.to_mbar() * dive.water.mm_per_mbar();
+    let grid = |mm: f64| Depth::millimeters((mm / step).round() * step);
+    let first = grid(max_depth.to_mm() / 2.0);
+    let switch = grid(Depth::meters(RATIO_SWITCH).to_mm());
+    let last_spread = grid(Depth::meters(RATIO_LAST_SPREAD).to_mm());
+    let o2 = grid(Depth::meters(RATIO_O2_STOP).to_mm());
+
+    let mut stops: Vec<(Depth, f64)> = Vec::new();
+    let mut depth = first.to_mm();
+    while depth > switch.to_mm() + 1.0 {
+        stops.push((Depth::millimeters(depth), 1.0));
+        depth -= step;
+    }
+    let mut spread = Vec::new();
+    while depth >= last_spread.to_mm() - 1.0 {
+        spread.push(Depth::millimeters(depth));
+        depth -= step;
+    }
+    let remaining = (bottom_time * ratio - stops.len() as f64).max(0.0);
+    let at_o2 = (remaining / 2.0).ceil();
+    let rest = remaining - at_o2;
+    if spread.is_empty() {
+        stops.push((o2, remaining));
+        return stops;
+    }
+    let each = (rest / spread.len() as f64).floor();
+    // Extra minutes go to the shallower stops.
+    let mut extra = rest - each * spread.len() as f64;
+    let count = spread.len();
+    for (i, d) in spread.into_iter().enumerate() {
+        let time = if (count - i) as f64 <= extra {
+            extra -= 1.0;
+            each + 1.0
+        } else {
+            each
+        };
+        if time > 0.0 {
+            stops.push((d, time));
+        }
+    }
+    stops.push((o2, at_o2));
+    stops
+}
+
+/// Plans the dive with Ratio Deco stops (first stop at half the max depth,
+/// deco time of bottom time times ratio) and runs it through the tissue model
+/// so it can be compared with calc_deco. Stop times exclude the ascent to the
+/// stop and deco gasses switch at the stop nearest their MOD (O2 at 6m).
+pub fn ratio_deco(
+    dive: &Dive,
+    compartments: &Compartments,
+    constants: &TissueConstants,
+    segments_in: &[SegmentIn],
+    gasses: &[Gas],
+    ratio: f64,
+) -> Result<DivePlan, String> {
+    if segments_in.is_empty() {
+        return Err("Must provide segment(s) to calculate deco against.".to_string());
+    }
+    check_compartments(compartments, constants)?;
+    let gasses: Vec<Gas> = gasses
+        .iter()
+        .map(|g| {
+            let stop = stop_grid(dive, g.max_pressure());
+            if g.role == GasRole::Deco && stop > g.max_pressure() {
+                g.with_mod(
+                    stop.to_depth(dive.atm_pressure, dive.water),
+                    dive.atm_pressure,
+                    dive.water,
+                )
+            } else {
+                *g
+            }
+        })
+        .collect();
+    let (mut segments, comps_out, last_depth) =
+        initial_segments(dive, compartments, constants, segments_in, &gasses)?;
+    let bottom_segments = segments.len();
+    let max_depth = segments_in
+        .iter()
+        .map(|s| s.depth)
+        .fold(Depth::meters(0.0), |a, b| if b > a { b } else { a });
+    let bottom_time: f64 = segments_in.iter().map(|s| s.time).sum();
+    // Ascend to each stop first so the stop gets its full time.
+    let mut profile: Vec<SegmentIn> = ratio_deco_stops(dive, max_depth, bottom_time, ratio)
+        .into_iter()
+        .flat_map(|(depth, time)| {
+            [
+                SegmentIn::new(SegmentType::UP, depth, 0.0, dive.deco_setpoint),
+                SegmentIn::new(SegmentType::LEVEL, depth, time, dive.deco_setpoint),
+            ]
+        })
+        .collect();
+    profile.push(SegmentIn::new(
+        SegmentType::UP,
+        Depth::meters(0.0),
+        0.0,
+        dive.deco_setpoint,
+    ));
+    let (mut newsegs, comps_out) =
+        run_profile(dive, constants, &gasses, &comps_out, last_depth, &profile)?;
+    segments.append(&mut newsegs);
+    for seg in segments.iter_mut() {
+        tissue_state(dive, constants, seg, dive.gf_hi);
+    }
+    oxygen_warnings(dive, &mut segments);
+    Ok(DivePlan::new(dive, segments, bottom_segments, comps_out))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn ratio() {
+        let dive: Dive = Default::default();
+        let stops = ratio_deco_stops(&dive, Depth::meters(60.0), 30.0, 1.0);
+        let depths: Vec<f64> = stops.iter().map(|s| s.0.to_meters().round()).collect();
+        assert_eq!(
+            vec![30.0, 27.0, 24.0, 21.0, 18.0, 15.0, 12.0, 9.0, 6.0],
+            depths
+        );
+        let total: f64 = stops.iter().map(|s| s.1).sum();
+        assert_eq!(30.0, total);
+        assert_eq!(14.0, stops[stops.len() - 1].1);
+
+        let comps = Compartments::new_surface(1013.0, Alveolar::default(), COMPARTMENTS);
+        let segments = vec![SegmentIn::new_bottom(Depth::meters(60.0), 30.0, 1.4)];
+        let gasses = vec![
+            Gas::new_bottom(0.18, 0.45, 1.4),
+            Gas::new_deco(0.5, 0.0),
+            Gas::new_deco(1.0, 0.0),
+        ];
+        let plan = ratio_deco(&dive, &comps, &CONSTANTS_C, &segments, &gasses, 1.0).unwrap();
+        let last = &plan.segments[plan.segments.len() - 1];
+        assert_eq!(0.0, (last.depth - dive.atm_pressure).to_mbar());
+        // On 50% from 21m.
+        let switch = plan
+            .segments
+            .iter()
+            .find(|s| format!("{}", s.gas) == "50%")
+            .unwrap();
+        assert_eq!(
+            21.0,
+            switch
+                .depth
+                .to_depth(dive.atm_pressure, dive.water)
+                .to_meters()
+                .round()
+        );
+        assert!(last.surface_gf > 0.0);
+        // The stops add up to bottom time times ratio and 6m is on O2.
+        assert_eq!(30, plan.deco_time);
+        let planned: Vec<u32> = plan.segments[plan.bottom_segments..]
+            .iter()
+            .filter(|s| s.segment_type == SegmentType::LEVEL)
+            .map(|s| s.time)
+            .collect();
+        let expected: Vec<u32> = stops.iter().map(|s| s.1 as u32).collect();
+        assert_eq!(expected, planned);
+        let o2 = plan
+            .segments
+            .iter()
+            .find(|s| s.segment_type == SegmentType::LEVEL && s.time == 14)
+            .unwrap();
+        assert_eq!(
+            6.0,
+            o2.depth
+                .to_depth(dive.atm_pressure, dive.water)
+                .to_meters()
+                .round()
+        );
+        assert_eq!(1.0, o2.gas.f_o2);
+    }
+}
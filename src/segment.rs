@@ -1,3 +1,4 @@
+use crate::alveolar::Alveolar;
 use crate::gas::Gas;
 use crate::otu_cns::OtuCns;
 use crate::segment_type::SegmentType;
@@ -12,7 +13,7 @@ pub struct SegmentIn {
 }
 
 impl SegmentIn {
-    fn new(segment_type: SegmentType, depth: Depth, time: f64, setpoint: f64) -> SegmentIn {
+    pub fn new(segment_type: SegmentType, depth: Depth, time: f64, setpoint: f64) -> SegmentIn {
         SegmentIn {
             segment_type,
             depth,
@@ -31,10 +32,16 @@ pub struct Segment {
     pub raw_time: f64,
     pub time: u32,
     pub gas: Gas,
-    pub ceiling: i32,
+    pub ceiling: Pressure, // at the GF in effect, at the end of the segment
+    pub leading_compartment: usize, // compartment setting the ceiling
+    pub gf99: f64,         // % of the M-value at the segment depth
+    pub surface_gf: f64,   // % of the M-value if surfacing now
     pub otu_cns: OtuCns,
     pub setpoint: f64,
     pub compartments: Compartments,
+    pub end: Depth,   // at the deepest point of the segment
+    pub density: f64, // g/L at the deepest point of the segment
+    pub warnings: Vec<String>,
 }
 
 /*impl Segment {
@@ -53,12 +60,12 @@ pub struct Compartments {
 impl Compartments {
     pub fn new_surface(
         atm_pressure: f64,
-        partial_water: f64,
+        alveolar: Alveolar,
         num_compartments: usize,
     ) -> Compartments {
         let mut nitrogen = Vec::with_capacity(num_compartments);
         let mut helium = Vec::with_capacity(num_compartments);
-        let n2_partial = 0.79 * ((atm_pressure - partial_water) / 1000.0);
+        let n2_partial = alveolar.inspired(Pressure::millibar(atm_pressure), 0.79);
         for _i in 0..num_compartments {
             nitrogen.push(n2_partial);
             helium.push(0.0);
@@ -66,6 +73,19 @@ impl Compartments {
         Compartments { nitrogen, helium }
     }
 
+    // Equilibrium with air at altitude (meters above sea level).
+    pub fn new_altitude(
+        altitude: f64,
+        alveolar: Alveolar,
+        num_compartments: usize,
+    ) -> Compartments {
+        Compartments::new_surface(
+            Pressure::from_altitude(altitude).to_mbar(),
+            alveolar,
+            num_compartments,
+        )
+    }
+
     pub fn new_empty(num_compartments: usize) -> Compartments {
         let mut nitrogen = Vec::with_capacity(num_compartments);
         let mut helium = Vec::with_capacity(num_compartments);
@@ -1,4 +1,51 @@
 use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, AddAssign, Mul, Sub, SubAssign};
+use std::str::FromStr;
+
+// Standard gravity in m/s², used to convert water column to pressure.
+const GRAVITY: f64 = 9.80665;
+
+// Water density used for all depth <-> pressure conversions.
+#[derive(Copy, Clone, PartialEq, Default)]
+pub enum Water {
+    Fresh, // 1000 kg/m³
+    #[default]
+    EN13319, // 1020 kg/m³
+    Salt,  // 1030 kg/m³
+    Custom(f64), // kg/m³
+}
+
+impl Water {
+    // Water of density kg/m³, which must be positive.
+    pub fn custom(density: f64) -> Result<Water, String> {
+        Water::Custom(density).validate()
+    }
+
+    // Errors for a Custom density that is not a positive number.
+    pub fn validate(self) -> Result<Water, String> {
+        let density = self.density();
+        if density.is_finite() && density > 0.0 {
+            Ok(self)
+        } else {
+            Err(format!("Water density {} must be positive.", density))
+        }
+    }
+
+    pub fn density(&self) -> f64 {
+        match self {
+            Water::Fresh => 1000.0,
+            Water::EN13319 => 1020.0,
+            Water::Salt => 1030.0,
+            Water::Custom(density) => *density,
+        }
+    }
+
+    // Depth of water column (mm) that exerts 1 mbar.
+    pub fn mm_per_mbar(&self) -> f64 {
+        100_000.0 / (self.density() * GRAVITY)
+    }
+}
 
 // Use f64 to avoid casting for calculations.  Normalized to mm.
 #[derive(Copy, Clone)]
@@ -13,6 +60,13 @@ pub struct Pressure(f64);
 #[derive(Copy, Clone)]
 pub struct DepthChange(f64);
 
+// Use f64 to avoid casting for math.  Normalized to liters (at surface pressure).
+#[derive(Copy, Clone)]
+pub struct Volume(f64);
+
+const MBAR_PER_PSI: f64 = 68.947_572_9;
+const LITERS_PER_CUFT: f64 = 28.316_846_6;
+
 impl Depth {
     pub fn millimeters(mm: f64) -> Depth {
         Depth(mm)
@@ -23,8 +77,8 @@ impl Depth {
     pub fn feet(feet: f64) -> Depth {
         Depth(feet * 304.8)
     }
-    pub fn from_pressure(pressure: Pressure, atm: Pressure) -> Depth {
-        Depth((pressure.to_mbar() - atm.to_mbar()) * 10.0)
+    pub fn from_pressure(pressure: Pressure, atm: Pressure, water: Water) -> Depth {
+        Depth((pressure.to_mbar() - atm.to_mbar()) * water.mm_per_mbar())
     }
 
     pub fn to_mm(&self) -> f64 {
@@ -67,11 +121,18 @@ impl Pressure {
     pub fn bar(bar: f64) -> Pressure {
         Pressure(bar * 1000.0)
     }
-    pub fn from_depth(depth: Depth, atm: Pressure) -> Pressure {
-        Pressure((depth.to_mm() / 10.0) + atm.to_mbar())
+    pub fn psi(psi: f64) -> Pressure {
+        Pressure(psi * MBAR_PER_PSI)
+    }
+    // Standard atmosphere barometric formula, altitude in meters above sea level.
+    pub fn from_altitude(meters: f64) -> Pressure {
+        Pressure(1013.25 * (1.0 - 2.25577e-5 * meters).powf(5.25588))
     }
-    pub fn from_depth_rel(depth: Depth) -> Pressure {
-        Pressure(depth.to_mm() / 10.0)
+    pub fn from_depth(depth: Depth, atm: Pressure, water: Water) -> Pressure {
+        Pressure((depth.to_mm() / water.mm_per_mbar()) + atm.to_mbar())
+    }
+    pub fn from_depth_rel(depth: Depth, water: Water) -> Pressure {
+        Pressure(depth.to_mm() / water.mm_per_mbar())
     }
 
     pub fn to_mbar(&self) -> f64 {
@@ -80,8 +141,11 @@ impl Pressure {
     pub fn to_bar(&self) -> f64 {
         self.0 / 1000.0
     }
-    pub fn to_depth(&self, atm: Pressure) -> Depth {
-        Depth::from_pressure(*self, atm)
+    pub fn to_psi(&self) -> f64 {
+        self.0 / MBAR_PER_PSI
+    }
+    pub fn to_depth(&self, atm: Pressure, water: Water) -> Depth {
+        Depth::from_pressure(*self, atm, water)
     }
 }
 impl PartialEq for Pressure {
@@ -111,14 +175,14 @@ impl DepthChange {
     pub fn descent_pressure(mbar: Pressure) -> DepthChange {
         DepthChange(mbar.to_mbar())
     }
-    pub fn descent_depth(depth: Depth) -> DepthChange {
-        DepthChange(depth.to_mm() / 10.0)
+    pub fn descent_depth(depth: Depth, water: Water) -> DepthChange {
+        DepthChange(depth.to_mm() / water.mm_per_mbar())
     }
     pub fn ascent_pressure(mbar: Pressure) -> DepthChange {
         DepthChange(-mbar.to_mbar())
     }
-    pub fn ascent_depth(depth: Depth) -> DepthChange {
-        DepthChange(-depth.to_mm() / 10.0)
+    pub fn ascent_depth(depth: Depth, water: Water) -> DepthChange {
+        DepthChange(-depth.to_mm() / water.mm_per_mbar())
     }
 
     pub fn to_mbar(&self) -> f64 {
@@ -127,6 +191,10 @@ impl DepthChange {
     pub fn to_bar(&self) -> f64 {
         self.0 / 1000.0
     }
+    // Depth change per minute, negative on ascent.
+    pub fn to_depth(&self, water: Water) -> Depth {
+        Depth(self.0 * water.mm_per_mbar())
+    }
 }
 impl PartialEq for DepthChange {
     fn eq(&self, other: &DepthChange) -> bool {
@@ -150,3 +218,227 @@ impl Ord for DepthChange {
         Ordering::Greater
     }
 }
+
+impl Volume {
+    pub fn liters(liters: f64) -> Volume {
+        Volume(liters)
+    }
+    pub fn cubic_feet(cuft: f64) -> Volume {
+        Volume(cuft * LITERS_PER_CUFT)
+    }
+
+    pub fn to_liters(&self) -> f64 {
+        self.0
+    }
+    pub fn to_cubic_feet(&self) -> f64 {
+        self.0 / LITERS_PER_CUFT
+    }
+}
+
+macro_rules! impl_ops {
+    ($t:ident) => {
+        impl Add for $t {
+            type Output = $t;
+            fn add(self, other: $t) -> $t {
+                $t(self.0 + other.0)
+            }
+        }
+        impl AddAssign for $t {
+            fn add_assign(&mut self, other: $t) {
+                self.0 += other.0;
+            }
+        }
+        impl Sub for $t {
+            type Output = $t;
+            fn sub(self, other: $t) -> $t {
+                $t(self.0 - other.0)
+            }
+        }
+        impl SubAssign for $t {
+            fn sub_assign(&mut self, other: $t) {
+                self.0 -= other.0;
+            }
+        }
+        impl Mul<f64> for $t {
+            type Output = $t;
+            fn mul(self, scalar: f64) -> $t {
+                $t(self.0 * scalar)
+            }
+        }
+    };
+}
+impl_ops!(Depth);
+impl_ops!(Pressure);
+impl_ops!(DepthChange);
+impl_ops!(Volume);
+
+// Split "60m", "14.7 psi" or "-9m/min" into value and lower case unit.
+fn split_unit(s: &str) -> Result<(f64, String), String> {
+    let s = s.trim();
+    let idx = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+        .unwrap_or(s.len());
+    let (num, unit) = s.split_at(idx);
+    let value = num
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid number in '{}'.", s))?;
+    Ok((value, unit.trim().to_lowercase()))
+}
+
+impl Depth {
+    // Depth in meters or feet with precision decimal places.
+    pub fn format(&self, metric: bool, precision: usize) -> String {
+        if metric {
+            format!("{:.*}m", precision, self.to_meters())
+        } else {
+            format!("{:.*}ft", precision, self.to_feet())
+        }
+    }
+}
+// Meters by default, feet with {:#}, precision defaults to 1.
+impl fmt::Display for Depth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.format(!f.alternate(), f.precision().unwrap_or(1))
+        )
+    }
+}
+impl FromStr for Depth {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Depth, String> {
+        let (value, unit) = split_unit(s)?;
+        match unit.as_str() {
+            "m" | "meter" | "meters" => Ok(Depth::meters(value)),
+            "mm" => Ok(Depth::millimeters(value)),
+            "ft" | "feet" | "'" => Ok(Depth::feet(value)),
+            _ => Err(format!("Unknown depth unit in '{}'.", s)),
+        }
+    }
+}
+
+impl Pressure {
+    // Pressure in bar or psi with precision decimal places.
+    pub fn format(&self, metric: bool, precision: usize) -> String {
+        if metric {
+            format!("{:.*}bar", precision, self.to_bar())
+        } else {
+            format!("{:.*}psi", precision, self.to_psi())
+        }
+    }
+}
+// Bar by default, psi with {:#}, precision defaults to 3.
+impl fmt::Display for Pressure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.format(!f.alternate(), f.precision().unwrap_or(3))
+        )
+    }
+}
+impl FromStr for Pressure {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Pressure, String> {
+        let (value, unit) = split_unit(s)?;
+        match unit.as_str() {
+            "bar" => Ok(Pressure::bar(value)),
+            "mbar" => Ok(Pressure::millibar(value)),
+            "psi" => Ok(Pressure::psi(value)),
+            "atm" => Ok(Pressure::millibar(value * 1013.25)),
+            _ => Err(format!("Unknown pressure unit in '{}'.", s)),
+        }
+    }
+}
+
+impl DepthChange {
+    // Rate in m/min or ft/min with precision decimal places, negative on ascent.
+    pub fn format(&self, metric: bool, precision: usize, water: Water) -> String {
+        let depth = self.to_depth(water);
+        if metric {
+            format!("{:.*}m/min", precision, depth.to_meters())
+        } else {
+            format!("{:.*}ft/min", precision, depth.to_feet())
+        }
+    }
+
+    // Parse "9m/min", "30ft/min" or "1bar/min", negative for ascent.
+    pub fn parse(s: &str, water: Water) -> Result<DepthChange, String> {
+        let (value, unit) = split_unit(s)?;
+        match unit.as_str() {
+            "m/min" => Ok(DepthChange::descent_depth(Depth::meters(value), water)),
+            "ft/min" => Ok(DepthChange::descent_depth(Depth::feet(value), water)),
+            "bar/min" => Ok(DepthChange::descent_pressure(Pressure::bar(value))),
+            "mbar/min" => Ok(DepthChange::descent_pressure(Pressure::millibar(value))),
+            _ => Err(format!("Unknown rate unit in '{}'.", s)),
+        }
+    }
+}
+// m/min (default water) by default, ft/min with {:#}, precision defaults to 1.
+impl fmt::Display for DepthChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.format(!f.alternate(), f.precision().unwrap_or(1), Water::default())
+        )
+    }
+}
+impl FromStr for DepthChange {
+    type Err = String;
+    fn from_str(s: &str) -> Result<DepthChange, String> {
+        DepthChange::parse(s, Water::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn parse_and_display() {
+        let depth: Depth = "60m".parse().unwrap();
+        assert_eq!(60000.0, depth.to_mm());
+        let depth: Depth = "200 ft".parse().unwrap();
+        assert_eq!("200ft", format!("{:#.0}", depth));
+        assert_eq!("61.0m", format!("{}", depth));
+        let pressure: Pressure = "1.013bar".parse().unwrap();
+        assert_eq!("1.013bar", format!("{}", pressure));
+        let pressure: Pressure = "14.7psi".parse().unwrap();
+        assert_eq!("14.7psi", format!("{:#.1}", pressure));
+        let rate: DepthChange = "9m/min".parse().unwrap();
+        assert_eq!("9.0m/min", format!("{}", rate));
+        let rate: DepthChange = "-30ft/min".parse().unwrap();
+        assert_eq!("-30ft/min", format!("{:#.0}", rate));
+        assert!("60".parse::<Depth>().is_err());
+        assert!("m".parse::<Depth>().is_err());
+    }
+
+    #[test]
+    fn arithmetic() {
+        let depth = Depth::meters(21.0) + Depth::meters(3.0) * 2.0 - Depth::meters(6.0);
+        assert_eq!(21.0, depth.to_meters());
+        let mut pressure = Pressure::bar(1.0);
+        pressure += Pressure::millibar(13.0);
+        assert_eq!(1013.0, pressure.to_mbar());
+    }
+
+    #[test]
+    fn water_round_trip() {
+        let atm = Pressure::millibar(1013.0);
+        let depth = Depth::meters(40.0);
+        for water in [Water::Fresh, Water::Salt, Water::custom(1025.0).unwrap()] {
+            let pressure = Pressure::from_depth(depth, atm, water);
+            let back = Depth::from_pressure(pressure, atm, water);
+            assert!((back.to_mm() - depth.to_mm()).abs() < 1e-6);
+        }
+        // 10m of fresh water is 980.665mbar, salt water is denser.
+        let fresh = Pressure::from_depth_rel(Depth::meters(10.0), Water::Fresh);
+        assert!((fresh.to_mbar() - 980.665).abs() < 1e-9);
+        assert!(Pressure::from_depth_rel(Depth::meters(10.0), Water::Salt) > fresh);
+        assert!(Water::custom(0.0).is_err());
+        assert!(Water::custom(-1000.0).is_err());
+        assert!(Water::Custom(f64::NAN).validate().is_err());
+    }
+}